@@ -1,52 +1,173 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::process::Command;
 
+use crate::chunk::{Container, MuxOpts};
+use crate::tools::ShowCmd;
+
+#[derive(Clone, Copy)]
+pub enum Downmix {
+    Film,
+    Music,
+    Dialog,
+}
+
+impl Downmix {
+    const fn pan(self) -> &'static str {
+        match self {
+            Self::Film => {
+                "pan=stereo|FL=FL+0.707*FC+0.707*SL+0.5*BL+0.5*BC|FR=FR+0.707*FC+0.707*SR+0.5*BR+0.\
+                 5*BC"
+            }
+            Self::Music => {
+                "pan=stereo|FL=FL+0.5*FC+0.707*SL+0.707*BL+0.5*BC|FR=FR+0.5*FC+0.707*SR+0.707*BR+0.\
+                 5*BC"
+            }
+            Self::Dialog => {
+                "pan=stereo|FL=0.707*FL+FC+0.5*SL+0.35*BL+0.35*BC|FR=0.707*FR+FC+0.5*SR+0.35*BR+0.\
+                 35*BC"
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Loudnorm {
+    pub i: f32,
+    pub tp: f32,
+    pub lra: f32,
+    pub mix: Downmix,
+}
+
+impl Default for Loudnorm {
+    fn default() -> Self {
+        Self { i: -14.0, tp: -2.5, lra: 14.0, mix: Downmix::Film }
+    }
+}
+
 #[derive(Clone)]
 pub enum AudioBitrate {
     Auto,
     Fixed(u32),
-    Norm,
+    PerStream(HashMap<usize, u32>),
+    Norm(Loudnorm),
 }
 
 #[derive(Clone)]
 pub enum AudioStreams {
     All,
     Specific(Vec<usize>),
+    ByLang(Vec<String>),
+}
+
+#[derive(Clone, Default)]
+pub struct AudioDelay {
+    all: i64,
+    streams: HashMap<usize, i64>,
+}
+
+impl AudioDelay {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut delay = Self::default();
+        for part in s.split(',') {
+            if let Some((id, ms)) = part.split_once(':') {
+                delay.streams.insert(id.parse()?, ms.parse()?);
+            } else {
+                delay.all = part.parse()?;
+            }
+        }
+        Ok(delay)
+    }
+
+    fn of(&self, index: usize) -> i64 {
+        self.streams.get(&index).copied().unwrap_or(self.all)
+    }
 }
 
 #[derive(Clone)]
 pub struct AudioSpec {
     pub bitrate: AudioBitrate,
     pub streams: AudioStreams,
+    pub delay: AudioDelay,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 struct AudioStream {
     index: usize,
     channels: u32,
     lang: Option<String>,
+    title: Option<String>,
+    default: bool,
+    forced: bool,
 }
 
 pub fn parse_audio_arg(arg: &str) -> Result<AudioSpec, Box<dyn std::error::Error>> {
     let parts: Vec<&str> = arg.split_whitespace().collect();
     if parts.len() != 2 {
-        return Err("Audio format: -a <auto|norm|bitrate> <all|stream_ids>".into());
+        return Err("Audio format: -a <auto|norm|bitrate> <all|stream_ids|languages>".into());
+    }
+
+    let streams = if parts[1] == "all" {
+        AudioStreams::All
+    } else if parts[1].split(',').all(|p| p.chars().all(|c| c.is_ascii_alphabetic())) {
+        AudioStreams::ByLang(parts[1].split(',').map(str::to_lowercase).collect())
+    } else {
+        AudioStreams::Specific(parts[1].split(',').map(str::parse).collect::<Result<_, _>>()?)
+    };
+
+    let bitrate = match parts[0] {
+        "auto" => AudioBitrate::Auto,
+        "norm" => AudioBitrate::Norm(Loudnorm::default()),
+        b if b.starts_with("norm:") => AudioBitrate::Norm(parse_loudnorm(&b[5..])?),
+        b if b.contains(',') => {
+            let rates: Vec<u32> = b.split(',').map(str::parse).collect::<Result<_, _>>()?;
+            let AudioStreams::Specific(ids) = &streams else {
+                return Err("Per-stream bitrates need explicit stream ids".into());
+            };
+            if rates.len() != ids.len() {
+                return Err(format!(
+                    "Got {} bitrates for {} audio streams",
+                    rates.len(),
+                    ids.len()
+                )
+                .into());
+            }
+            AudioBitrate::PerStream(ids.iter().copied().zip(rates).collect())
+        }
+        _ => AudioBitrate::Fixed(parts[0].parse()?),
+    };
+
+    Ok(AudioSpec { bitrate, streams, delay: AudioDelay::default() })
+}
+
+fn parse_loudnorm(s: &str) -> Result<Loudnorm, Box<dyn std::error::Error>> {
+    let (mix, rest) = match s.split_once(':').unwrap_or((s, "")) {
+        ("film", rest) => (Downmix::Film, rest),
+        ("music", rest) => (Downmix::Music, rest),
+        ("dialog", rest) => (Downmix::Dialog, rest),
+        _ => (Downmix::Film, s),
+    };
+    if rest.is_empty() {
+        return Ok(Loudnorm { mix, ..Loudnorm::default() });
+    }
+
+    let v: Vec<f32> = rest.split(':').map(str::parse).collect::<Result<_, _>>()?;
+    let [i, tp, lra] = v[..] else {
+        return Err("Loudnorm format: norm[:film|music|dialog][:<I>:<TP>:<LRA>]".into());
+    };
+
+    if !(-70.0..=-5.0).contains(&i) {
+        return Err(format!("Loudnorm I {i} must be between -70 and -5 LUFS").into());
+    }
+    if !(-9.0..=0.0).contains(&tp) {
+        return Err(format!("Loudnorm TP {tp} must be between -9 and 0 dBTP").into());
+    }
+    if !(1.0..=50.0).contains(&lra) {
+        return Err(format!("Loudnorm LRA {lra} must be between 1 and 50 LU").into());
     }
 
-    Ok(AudioSpec {
-        bitrate: match parts[0] {
-            "auto" => AudioBitrate::Auto,
-            "norm" => AudioBitrate::Norm,
-            _ => AudioBitrate::Fixed(parts[0].parse()?),
-        },
-        streams: if parts[1] == "all" {
-            AudioStreams::All
-        } else {
-            AudioStreams::Specific(parts[1].split(',').map(str::parse).collect::<Result<_, _>>()?)
-        },
-    })
+    Ok(Loudnorm { i, tp, lra, mix })
 }
 
 fn lang_name(code: &str) -> &str {
@@ -124,31 +245,34 @@ fn get_streams(input: &Path) -> Result<Vec<AudioStream>, Box<dyn std::error::Err
             "-select_streams",
             "a",
             "-show_entries",
-            "stream=index,channels:stream_tags=language",
+            "stream=index,channels:stream_disposition=default,forced:stream_tags=language,title",
             "-of",
-            "csv=p=0",
+            "default",
         ])
         .arg(input)
         .output()?;
 
-    let mut seen = HashSet::new();
-    let mut streams: Vec<_> = String::from_utf8_lossy(&out.stdout)
-        .lines()
-        .rev()
-        .filter_map(|l| {
-            let p: Vec<_> = l.split(',').collect();
-            (p.len() >= 2).then(|| {
-                let idx = p[0].parse().ok()?;
-                seen.insert(idx).then(|| AudioStream {
-                    index: idx,
-                    channels: p[1].parse().unwrap_or(2),
-                    lang: p.get(2).filter(|s| !s.is_empty()).map(std::string::ToString::to_string),
-                })
-            })?
-        })
-        .collect();
-    streams.reverse();
+    let mut streams: Vec<AudioStream> = Vec::new();
+    for line in String::from_utf8_lossy(&out.stdout).lines() {
+        if line == "[STREAM]" {
+            streams.push(AudioStream { channels: 2, ..AudioStream::default() });
+            continue;
+        }
+        let (Some(s), Some((key, val))) = (streams.last_mut(), line.split_once('=')) else {
+            continue;
+        };
+        match key {
+            "index" => s.index = val.parse().unwrap_or_default(),
+            "channels" => s.channels = val.parse().unwrap_or(2),
+            "DISPOSITION:default" => s.default = val == "1",
+            "DISPOSITION:forced" => s.forced = val == "1",
+            "TAG:language" if !val.is_empty() => s.lang = Some(val.to_string()),
+            "TAG:title" if !val.is_empty() => s.title = Some(val.to_string()),
+            _ => {}
+        }
+    }
     streams.sort_by_key(|s| s.index);
+    streams.dedup_by_key(|s| s.index);
     Ok(streams)
 }
 
@@ -157,7 +281,7 @@ fn encode_stream(
     stream: &AudioStream,
     bitrate: u32,
     output: &Path,
-    normalize: bool,
+    normalize: Option<Loudnorm>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::new("ffmpeg");
     cmd.args(["-loglevel", "error", "-hide_banner", "-nostdin", "-stats", "-y", "-i"])
@@ -165,12 +289,14 @@ fn encode_stream(
         .args(["-map_metadata", "-1", "-map_chapters", "-1", "-dn", "-sn", "-vn", "-map"])
         .arg(format!("0:{}", stream.index));
 
-    if normalize {
-        cmd.args([
-            "-af",
-            "pan=stereo|FL=FL+0.707*FC+0.707*SL+0.5*BL+0.5*BC|FR=FR+0.707*FC+0.707*SR+0.5*BR+0.5*\
-             BC,loudnorm=I=-14:TP=-2.5:LRA=14",
-        ]);
+    if let Some(ln) = normalize {
+        cmd.arg("-af").arg(format!(
+            "{},loudnorm=I={}:TP={}:LRA={}",
+            ln.mix.pan(),
+            ln.i,
+            ln.tp,
+            ln.lra
+        ));
     }
 
     cmd.args([
@@ -189,7 +315,7 @@ fn encode_stream(
         "-vbr",
         "on",
         "-mapping_family",
-        if normalize || stream.channels <= 2 { "0" } else { "1" },
+        if normalize.is_some() || stream.channels <= 2 { "0" } else { "1" },
         "-apply_phase_inv",
         "true",
         "-packet_loss",
@@ -202,6 +328,7 @@ fn encode_stream(
         "-ignore_unknown",
     ])
     .arg(output)
+    .show()
     .status()
     .ok()
     .filter(std::process::ExitStatus::success)
@@ -212,12 +339,21 @@ fn encode_stream(
 fn mux_files(
     video: &Path,
     files: &[(AudioStream, std::path::PathBuf)],
-    input: &Path,
+    (input, delay): (&Path, &AudioDelay),
     output: &Path,
     keep_all: bool,
+    opts: MuxOpts,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let webm = opts.container == Container::Webm;
     let mut cmd = Command::new("mkvmerge");
-    cmd.args(["-q", "-o"])
+    cmd.arg("-q");
+    if webm {
+        cmd.arg("--webm");
+    }
+    if let Some(tags) = opts.tags {
+        cmd.arg("--global-tags").arg(tags);
+    }
+    cmd.arg("-o")
         .arg(output)
         .args([
             "-A",
@@ -236,15 +372,35 @@ fn mux_files(
         cmd.arg("--language")
             .arg(format!("0:{code}"))
             .arg("--track-name")
-            .arg(format!("0:{}", lang_name(code)))
-            .arg(path);
+            .arg(format!("0:{}", info.title.as_deref().unwrap_or_else(|| lang_name(code))))
+            .arg("--default-track")
+            .arg(format!("0:{}", u8::from(info.default)))
+            .arg("--forced-track")
+            .arg(format!("0:{}", u8::from(info.forced)));
+        let ms = delay.of(info.index);
+        if ms != 0 {
+            cmd.arg("--sync").arg(format!("0:{ms}"));
+        }
+        cmd.arg(path);
     }
 
     cmd.args(["-D", "-B", "-M", "-T", "--no-global-tags"]);
-    if !keep_all {
+    if !keep_all || webm {
         cmd.arg("-A");
     }
+    if let Some(langs) = opts.subs
+        && !webm
+    {
+        cmd.arg("--subtitle-tracks").arg(langs.join(","));
+    }
+    if webm {
+        cmd.arg("-S");
+        if !opts.chapters {
+            cmd.arg("--no-chapters");
+        }
+    }
     cmd.arg(input)
+        .show()
         .status()
         .ok()
         .filter(std::process::ExitStatus::success)
@@ -252,64 +408,179 @@ fn mux_files(
     Ok(())
 }
 
-pub fn process_audio(
-    spec: &AudioSpec,
-    input: &Path,
+pub fn mux_mp4(
     video: &Path,
+    tracks: &[(&str, &Path, i64)],
+    chapters: Option<&Path>,
     output: &Path,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-loglevel", "error", "-hide_banner", "-nostdin", "-y", "-i"]).arg(video);
+    for (_, path, ms) in tracks {
+        if *ms != 0 {
+            cmd.arg("-itsoffset").arg(format!("{}", *ms as f64 / 1000.0));
+        }
+        cmd.arg("-i").arg(path);
+    }
+    if let Some(src) = chapters {
+        cmd.arg("-i").arg(src);
+    }
+
+    cmd.args(["-map", "0:v:0"]);
+    for (i, (lang, ..)) in tracks.iter().enumerate() {
+        cmd.arg("-map")
+            .arg(format!("{}:a:0", i + 1))
+            .arg(format!("-metadata:s:a:{i}"))
+            .arg(format!("language={lang}"));
+    }
+
+    let chap_idx = if chapters.is_some() { (tracks.len() + 1).to_string() } else { "-1".into() };
+    cmd.args(["-map_metadata", "-1", "-map_chapters"])
+        .arg(chap_idx)
+        .args(["-c", "copy", "-movflags", "+faststart"])
+        .arg(output)
+        .show()
+        .status()
+        .ok()
+        .filter(std::process::ExitStatus::success)
+        .ok_or("MP4 muxing failed")?;
+    Ok(())
+}
+
+pub fn mux_avif(video: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-hide_banner", "-nostdin", "-y", "-i"])
+        .arg(video)
+        .args(["-map", "0:v:0", "-map_metadata", "-1", "-c", "copy", "-f", "avif"])
+        .arg(output)
+        .show()
+        .status()
+        .ok()
+        .filter(std::process::ExitStatus::success)
+        .ok_or("AVIF muxing failed")?;
+    Ok(())
+}
+
+fn plan_streams(
+    spec: &AudioSpec,
+    input: &Path,
+) -> Result<Vec<(AudioStream, u32)>, Box<dyn std::error::Error>> {
     let all = get_streams(input)?;
     let sel: Vec<_> = match &spec.streams {
         AudioStreams::All => all.iter().collect(),
         AudioStreams::Specific(ids) => all.iter().filter(|s| ids.contains(&s.index)).collect(),
+        AudioStreams::ByLang(codes) => {
+            for code in codes {
+                if !all.iter().any(|s| s.lang.as_deref() == Some(code)) {
+                    eprintln!("Warning: no audio stream with language `{code}`");
+                }
+            }
+            all.iter().filter(|s| s.lang.as_ref().is_some_and(|l| codes.contains(l))).collect()
+        }
     };
 
-    let work = input.parent().unwrap();
-    let (use_norm, base_bitrate) = match &spec.bitrate {
-        AudioBitrate::Norm => (true, 128),
-        AudioBitrate::Auto | AudioBitrate::Fixed(_) => (false, 0),
-    };
-
-    let files: Vec<_> = sel
-        .iter()
+    Ok(sel
+        .into_iter()
         .map(|s| {
-            let br = if use_norm {
-                base_bitrate
-            } else {
-                match &spec.bitrate {
-                    AudioBitrate::Auto => {
-                        let cc = match s.channels {
-                            1 => 1.0,
-                            2 => 2.0,
-                            3 => 2.1,
-                            4 => 3.1,
-                            5 => 4.1,
-                            6 => 5.1,
-                            7 => 6.1,
-                            8 => 7.1,
-                            _ => f64::from(s.channels),
-                        };
-                        (128.0 * ((cc / 2.0) * 0.75)) as u32
-                    }
-                    AudioBitrate::Fixed(b) => *b,
-                    AudioBitrate::Norm => unreachable!(),
+            let br = match &spec.bitrate {
+                AudioBitrate::Norm(_) => 128,
+                AudioBitrate::Auto => {
+                    let cc = match s.channels {
+                        1 => 1.0,
+                        2 => 2.0,
+                        3 => 2.1,
+                        4 => 3.1,
+                        5 => 4.1,
+                        6 => 5.1,
+                        7 => 6.1,
+                        8 => 7.1,
+                        _ => f64::from(s.channels),
+                    };
+                    (128.0 * ((cc / 2.0) * 0.75)) as u32
                 }
+                AudioBitrate::Fixed(b) => *b,
+                AudioBitrate::PerStream(m) => m[&s.index],
             };
-            let path = work.join(
-                s.lang
-                    .as_ref()
-                    .map_or_else(|| format!("{:02}.opus", s.index), |l| format!("{l}.opus")),
-            );
-
-            encode_stream(input, s, br, &path, use_norm)?;
-            Ok::<_, Box<dyn std::error::Error>>(((*s).clone(), path))
+            (s.clone(), br)
         })
-        .collect::<Result<Vec<_>, _>>()?;
+        .collect())
+}
 
-    mux_files(video, &files, input, output, matches!(&spec.streams, AudioStreams::All))?;
+pub fn describe(spec: &AudioSpec, input: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let norm = matches!(spec.bitrate, AudioBitrate::Norm(_));
+    Ok(plan_streams(spec, input)?
+        .iter()
+        .map(|(s, br)| {
+            let ms = spec.delay.of(s.index);
+            format!(
+                "#{} {} {}ch -> Opus {br} kb/s{}{}",
+                s.index,
+                s.lang.as_deref().map_or("und", lang_name),
+                s.channels,
+                if norm { " (stereo, loudnorm)" } else { "" },
+                if ms == 0 { String::new() } else { format!(", delayed {ms} ms") }
+            )
+        })
+        .collect())
+}
 
+pub fn process_audio(
+    spec: &AudioSpec,
+    input: &Path,
+    video: &Path,
+    work: &Path,
+    output: &Path,
+    opts: MuxOpts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let res = encode_and_mux(spec, input, video, work, output, opts, &mut files);
     for (_, p) in &files {
         let _ = fs::remove_file(p);
     }
+    res
+}
+
+fn encode_and_mux(
+    spec: &AudioSpec,
+    input: &Path,
+    video: &Path,
+    work: &Path,
+    output: &Path,
+    opts: MuxOpts,
+    files: &mut Vec<(AudioStream, std::path::PathBuf)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let norm = match &spec.bitrate {
+        AudioBitrate::Norm(ln) => Some(*ln),
+        AudioBitrate::Auto | AudioBitrate::Fixed(_) | AudioBitrate::PerStream(_) => None,
+    };
+
+    for (s, br) in plan_streams(spec, input)? {
+        let path = work.join(s.lang.as_ref().map_or_else(
+            || format!("audio_{:02}.opus", s.index),
+            |l| format!("audio_{:02}_{l}.opus", s.index),
+        ));
+        files.push((s, path));
+        let (s, path) = files.last().unwrap();
+        encode_stream(input, s, br, path, norm)?;
+    }
+
+    if opts.container == Container::Mp4 {
+        let dropped = if opts.chapters { "subtitles are" } else { "subtitles and chapters are" };
+        eprintln!("Warning: {dropped} not carried into MP4 output");
+        let tracks: Vec<_> = files
+            .iter()
+            .map(|(s, p)| (s.lang.as_deref().unwrap_or("und"), p.as_path(), spec.delay.of(s.index)))
+            .collect();
+        mux_mp4(video, &tracks, opts.chapters.then_some(input), output)?;
+    } else {
+        mux_files(
+            video,
+            files,
+            (input, &spec.delay),
+            output,
+            matches!(&spec.streams, AudioStreams::All),
+            opts,
+        )?;
+    }
     Ok(())
 }
@@ -1,6 +1,13 @@
 use std::fs;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_channel::Sender;
+
+use crate::error::XavError;
+use crate::tools::ShowCmd;
 
 #[derive(Clone)]
 pub struct Scene {
@@ -25,12 +32,96 @@ pub struct ResumeInf {
     pub chnks_done: Vec<ChunkComp>,
 }
 
-pub fn load_scenes(path: &Path, t_frames: usize) -> Result<Vec<Scene>, Box<dyn std::error::Error>> {
+#[derive(Clone, Copy, PartialEq)]
+pub enum SceneLen {
+    Frames(usize),
+    Secs(f64),
+}
+
+impl SceneLen {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let len = match s.strip_suffix('s') {
+            Some(secs) => Self::Secs(secs.parse()?),
+            None => Self::Frames(s.parse()?),
+        };
+        match len {
+            Self::Frames(0) => Err("Scene length must be above 0".into()),
+            Self::Secs(secs) if secs <= 0.0 => Err("Scene length must be above 0".into()),
+            _ => Ok(len),
+        }
+    }
+
+    fn frames(self, fps_num: u32, fps_den: u32) -> usize {
+        match self {
+            Self::Frames(f) => f,
+            Self::Secs(secs) => (secs * f64::from(fps_num) / f64::from(fps_den)).round() as usize,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mkv,
+    Webm,
+    Mp4,
+    Avif,
+}
+
+impl Container {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        match ext.as_str() {
+            "webm" => Ok(Self::Webm),
+            "mp4" => Ok(Self::Mp4),
+            "avif" => Ok(Self::Avif),
+            "m4v" | "mov" => {
+                Err(format!("Cannot write .{ext} output, use .mkv, .webm or .mp4").into())
+            }
+            _ => Ok(Self::Mkv),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MuxOpts<'a> {
+    pub container: Container,
+    pub tags: Option<&'a Path>,
+    pub chapters: bool,
+    pub subs: Option<&'a [String]>,
+}
+
+fn parse_cut(line: &str, fps: f64) -> Option<usize> {
+    let line = line.trim();
+
+    if let Some(pos) = line.find("pts_time:") {
+        let secs: f64 = line[pos + 9..].split_whitespace().next()?.parse().ok()?;
+        return Some((secs * fps).round() as usize);
+    }
+
+    if let Some(pos) = line.find(" n:") {
+        return line[pos + 3..].split_whitespace().next()?.parse().ok();
+    }
+
+    let mut parts = line.split(|c: char| c.is_whitespace() || c == ',').filter(|p| !p.is_empty());
+    let frame = parts.next()?.parse().ok()?;
+    match parts.next() {
+        None | Some("I" | "K" | "i") => Some(frame),
+        Some(_) => None,
+    }
+}
+
+pub fn load_scenes(
+    path: &Path,
+    t_frames: usize,
+    fps: f64,
+) -> Result<Vec<Scene>, Box<dyn std::error::Error>> {
     let content = fs::read_to_string(path)?;
     let mut s_frames: Vec<usize> =
-        content.lines().filter_map(|line| line.trim().parse().ok()).collect();
+        content.lines().filter_map(|line| parse_cut(line, fps)).filter(|&f| f < t_frames).collect();
 
+    s_frames.push(0);
     s_frames.sort_unstable();
+    s_frames.dedup();
 
     let mut scenes = Vec::new();
     for i in 0..s_frames.len() {
@@ -42,19 +133,33 @@ pub fn load_scenes(path: &Path, t_frames: usize) -> Result<Vec<Scene>, Box<dyn s
     Ok(scenes)
 }
 
-pub fn validate_scenes(
-    scenes: &[Scene],
+pub fn scene_limits(
     fps_num: u32,
     fps_den: u32,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let min_len = (fps_num + fps_den / 2) / fps_den;
-    let max_len = ((fps_num * 10 + fps_den / 2) / fps_den).min(300);
+    (min, max): (Option<SceneLen>, Option<SceneLen>),
+) -> Result<(usize, usize), String> {
+    let def_min = ((fps_num + fps_den / 2) / fps_den) as usize;
+    let def_max = ((fps_num * 10 + fps_den / 2) / fps_den).min(300) as usize;
+    let min_len = min.map_or(def_min, |l| l.frames(fps_num, fps_den).max(1));
+    let max_len = max.map_or(def_max, |l| l.frames(fps_num, fps_den));
+    if min_len >= max_len {
+        return Err(format!(
+            "Minimum scene length ({min_len} frames) must be below the maximum ({max_len} frames)"
+        ));
+    }
+    Ok((min_len, max_len))
+}
 
+pub fn validate_scenes(
+    scenes: &[Scene],
+    min_len: usize,
+    max_len: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
     for (i, scene) in scenes.iter().enumerate() {
         let len = scene.e_frame.saturating_sub(scene.s_frame);
         let is_last = i == scenes.len() - 1;
 
-        if (!is_last && len < min_len as usize) || len > max_len as usize {
+        if (!is_last && len < min_len) || len > max_len {
             return Err(format!(
                 "Scene {} (frames {}-{}) has invalid length {}: must be between {} and {} frames",
                 i, scene.s_frame, scene.e_frame, len, min_len, max_len
@@ -66,12 +171,124 @@ pub fn validate_scenes(
     Ok(())
 }
 
-pub fn chunkify(scenes: &[Scene]) -> Vec<Chunk> {
-    scenes
+pub fn merge_short(scenes: &[Scene], min_len: usize, max_len: usize) -> Vec<Scene> {
+    let mut merged: Vec<Scene> = Vec::with_capacity(scenes.len());
+    for s in scenes {
+        if let Some(last) = merged.last_mut() {
+            let last_len = last.e_frame - last.s_frame;
+            let len = s.e_frame - s.s_frame;
+            if (last_len < min_len || len < min_len) && last_len + len <= max_len {
+                last.e_frame = s.e_frame;
+                continue;
+            }
+        }
+        merged.push(s.clone());
+    }
+    merged
+}
+
+pub fn trim_scenes(
+    scenes: &[Scene],
+    (start, end): (usize, usize),
+    min_len: usize,
+    max_len: usize,
+) -> Vec<Scene> {
+    let mut trimmed: Vec<Scene> = scenes
         .iter()
-        .enumerate()
-        .map(|(i, s)| Chunk { idx: i, start: s.s_frame, end: s.e_frame })
-        .collect()
+        .filter(|s| s.e_frame > start && s.s_frame < end)
+        .map(|s| Scene { s_frame: s.s_frame.max(start), e_frame: s.e_frame.min(end) })
+        .collect();
+
+    if trimmed.len() > 1 && trimmed[0].e_frame - trimmed[0].s_frame < min_len {
+        if trimmed[1].e_frame - start <= max_len {
+            trimmed[1].s_frame = start;
+            trimmed.remove(0);
+        } else {
+            trimmed[0].e_frame = start + min_len;
+            trimmed[1].s_frame = start + min_len;
+        }
+    }
+
+    trimmed
+}
+
+fn push_chunks(chunks: &mut Vec<Chunk>, s: &Scene, split: Option<usize>) {
+    let len = s.e_frame - s.s_frame;
+    let parts = split.map_or(1, |max| len.div_ceil(max).max(1));
+    for k in 0..parts {
+        chunks.push(Chunk {
+            idx: chunks.len(),
+            start: s.s_frame + len * k / parts,
+            end: s.s_frame + len * (k + 1) / parts,
+        });
+    }
+}
+
+pub fn chunkify(scenes: &[Scene], split: Option<usize>) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    for s in scenes {
+        push_chunks(&mut chunks, s, split);
+    }
+    chunks
+}
+
+pub struct SceneFeed {
+    tx: Sender<Chunk>,
+    merge: Option<(usize, usize)>,
+    split: Option<usize>,
+    last_cut: usize,
+    pending: Option<Scene>,
+    chunks: Vec<Chunk>,
+}
+
+impl SceneFeed {
+    pub const fn new(
+        tx: Sender<Chunk>,
+        merge: Option<(usize, usize)>,
+        split: Option<usize>,
+    ) -> Self {
+        Self { tx, merge, split, last_cut: 0, pending: None, chunks: Vec::new() }
+    }
+
+    pub fn cut(&mut self, frame: usize) {
+        if frame <= self.last_cut {
+            return;
+        }
+        let s = Scene { s_frame: self.last_cut, e_frame: frame };
+        self.last_cut = frame;
+
+        let Some((min_len, max_len)) = self.merge else {
+            self.emit(&s);
+            return;
+        };
+        if let Some(last) = self.pending.as_mut() {
+            let last_len = last.e_frame - last.s_frame;
+            let len = s.e_frame - s.s_frame;
+            if (last_len < min_len || len < min_len) && last_len + len <= max_len {
+                last.e_frame = s.e_frame;
+                return;
+            }
+        }
+        if let Some(last) = self.pending.replace(s) {
+            self.emit(&last);
+        }
+    }
+
+    fn emit(&mut self, s: &Scene) {
+        let first = self.chunks.len();
+        push_chunks(&mut self.chunks, s, self.split);
+        for c in &self.chunks[first..] {
+            let _ = self.tx.send(c.clone());
+        }
+    }
+
+    pub fn finish(mut self, frames: usize) -> Vec<Chunk> {
+        self.cut(frames);
+        if let Some(last) = self.pending.take() {
+            self.emit(&last);
+        }
+        self.chunks
+    }
 }
 
 pub fn get_resume(work_dir: &Path) -> Option<ResumeInf> {
@@ -118,10 +335,50 @@ pub fn save_resume(data: &ResumeInf, work_dir: &Path) -> Result<(), Box<dyn std:
     Ok(())
 }
 
+fn ivf_frames(path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut hdr = [0u8; 32];
+    if file.read_exact(&mut hdr).is_err() || &hdr[..4] != b"DKIF" {
+        return Err(format!("{} is not an IVF file", path.display()).into());
+    }
+
+    let mut pos = u64::from(u16::from_le_bytes([hdr[6], hdr[7]]));
+    let mut frames = 0;
+    let mut frame_hdr = [0u8; 12];
+    while pos + 12 <= len {
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut frame_hdr)?;
+        let size = u32::from_le_bytes([frame_hdr[0], frame_hdr[1], frame_hdr[2], frame_hdr[3]]);
+        pos += 12 + u64::from(size);
+        frames += 1;
+    }
+    Ok(frames)
+}
+
+pub fn verify_chunks(encode_dir: &Path, chunks: &[Chunk]) -> Result<(), XavError> {
+    let mut bad = Vec::new();
+    for c in chunks {
+        let path = encode_dir.join(format!("{:04}.ivf", c.idx));
+        match ivf_frames(&path) {
+            Ok(n) if n == c.end - c.start => {}
+            Ok(n) => bad.push(format!("{:04}: {n}/{} frames", c.idx, c.end - c.start)),
+            Err(e) => bad.push(format!("{:04}: {e}", c.idx)),
+        }
+    }
+
+    if bad.is_empty() {
+        Ok(())
+    } else {
+        Err(XavError::Encode(format!("Encoded chunks do not match the source: {}", bad.join(", "))))
+    }
+}
+
 pub fn merge_out(
     encode_dir: &Path,
     output: &Path,
     inf: &crate::ffms::VidInf,
+    opts: MuxOpts,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut files: Vec<_> = fs::read_dir(encode_dir)?
         .filter_map(Result::ok)
@@ -137,35 +394,98 @@ pub fn merge_out(
     });
 
     if files.len() <= 1024 {
-        return run_merge(&files.iter().map(fs::DirEntry::path).collect::<Vec<_>>(), output, inf);
+        return Ok(run_merge(
+            &files.iter().map(fs::DirEntry::path).collect::<Vec<_>>(),
+            output,
+            inf,
+            opts,
+        )?);
     }
 
     let temp_dir = encode_dir.join("temp_merge");
     fs::create_dir_all(&temp_dir)?;
 
-    let batches: Vec<_> = files
-        .chunks(1024)
-        .enumerate()
-        .map(|(i, chunk)| {
-            let path = temp_dir.join(format!("batch_{i}.ivf"));
-            run_merge(&chunk.iter().map(fs::DirEntry::path).collect::<Vec<_>>(), &path, inf)?;
-            Ok(path)
-        })
-        .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+    let groups: Vec<Vec<_>> =
+        files.chunks(1024).map(|c| c.iter().map(fs::DirEntry::path).collect()).collect();
+    let batches: Vec<_> =
+        (0..groups.len()).map(|i| temp_dir.join(format!("batch_{i}.ivf"))).collect();
 
-    run_merge(&batches, output, inf)?;
+    let next = AtomicUsize::new(0);
+    let threads =
+        std::thread::available_parallelism().map_or(4, std::num::NonZero::get).min(groups.len());
+    let failed = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= groups.len() {
+                            return Ok::<_, XavError>(());
+                        }
+                        let opts = MuxOpts {
+                            container: Container::Mkv,
+                            tags: None,
+                            chapters: false,
+                            subs: None,
+                        };
+                        run_merge(&groups[i], &batches[i], inf, opts)?;
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().filter_map(|h| h.join().unwrap().err()).next()
+    });
+    if let Some(e) = failed {
+        return Err(e.into());
+    }
+
+    run_merge(&batches, output, inf, opts)?;
     fs::remove_dir_all(&temp_dir)?;
     Ok(())
 }
 
+pub fn mux_chapters(
+    video: &Path,
+    input: &Path,
+    output: &Path,
+    opts: MuxOpts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::new("mkvmerge");
+    cmd.arg("-q");
+    if opts.container == Container::Webm {
+        cmd.arg("--webm");
+    }
+    let status = cmd
+        .arg("-o")
+        .arg(output)
+        .arg(video)
+        .args(["-D", "-A", "-S", "-B", "-T", "-M", "--no-global-tags"])
+        .arg(input)
+        .show()
+        .status()
+        .map_err(|e| XavError::Tool(format!("Failed to run mkvmerge: {e}")))?;
+
+    if !matches!(status.code(), Some(0 | 1)) {
+        return Err(XavError::Mux("Failed to mux chapters from input".into()).into());
+    }
+    Ok(())
+}
+
 fn run_merge(
     files: &[std::path::PathBuf],
     output: &Path,
     inf: &crate::ffms::VidInf,
-) -> Result<(), Box<dyn std::error::Error>> {
+    opts: MuxOpts,
+) -> Result<(), XavError> {
     let mut cmd = Command::new("mkvmerge");
-    cmd.arg("-q")
-        .arg("-o")
+    cmd.arg("-q");
+    if opts.container == Container::Webm {
+        cmd.arg("--webm");
+    }
+    if let Some(tags) = opts.tags {
+        cmd.arg("--global-tags").arg(tags);
+    }
+    cmd.arg("-o")
         .arg(output)
         .arg("-A")
         .arg("-S")
@@ -187,6 +507,10 @@ fn run_merge(
     }
 
     cmd.arg("--default-duration").arg(format!("0:{}/{}fps", inf.fps_num, inf.fps_den));
-    cmd.status()?;
+    let status =
+        cmd.show().status().map_err(|e| XavError::Tool(format!("Failed to run mkvmerge: {e}")))?;
+    if !matches!(status.code(), Some(0 | 1)) {
+        return Err(XavError::Mux(format!("mkvmerge failed to write {}", output.display())));
+    }
     Ok(())
 }
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use crate::ffms::{VidIdx, VidInf, calc_10bit_size, destroy_vid_src, extr_10bit, thr_vid_src};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Crop {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Crop {
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub const fn dims(&self, width: u32, height: u32) -> (u32, u32) {
+        (width - self.left - self.right, height - self.top - self.bottom)
+    }
+}
+
+fn from_ar(ar: f64, inf: &VidInf) -> Crop {
+    let (cur_dim, new_exact, is_vert) = if ar > f64::from(inf.width) / f64::from(inf.height) {
+        (inf.height, f64::from(inf.width) / ar, true)
+    } else {
+        (inf.width, f64::from(inf.height) * ar, false)
+    };
+
+    let mut new_dim = new_exact as u32;
+    let cur_mod4 = cur_dim % 4;
+    let new_mod4 = new_dim % 4;
+
+    if new_mod4 != cur_mod4 || new_exact.fract() != 0.0 {
+        let mut adj = (cur_mod4 + 4 - new_mod4) % 4;
+        if adj == 0 {
+            adj = 4;
+        }
+        new_dim += adj;
+    }
+
+    let crop = ((cur_dim - new_dim) / 2) & !1;
+    if is_vert {
+        Crop { top: crop, bottom: crop, left: 0, right: 0 }
+    } else {
+        Crop { top: 0, bottom: 0, left: crop, right: crop }
+    }
+}
+
+pub fn parse_crop(s: &str, inf: &VidInf) -> Result<Crop, Box<dyn std::error::Error>> {
+    let crop = if let Ok(ar) = s.parse::<f64>() {
+        from_ar(ar, inf)
+    } else {
+        let p = s
+            .split(',')
+            .map(|x| x.trim().parse::<u32>().map(|x| x & !1))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Crop `{s}` has a field that is not a whole number"))?;
+        match p[..] {
+            [v, h] => Crop { top: v, bottom: v, left: h, right: h },
+            [top, bottom, left, right] => Crop { top, bottom, left, right },
+            _ => {
+                return Err(format!(
+                    "Crop `{s}` needs 2 values (v,h), 4 values (t,b,l,r) or an aspect ratio"
+                )
+                .into());
+            }
+        }
+    };
+
+    if crop.top + crop.bottom >= inf.height || crop.left + crop.right >= inf.width {
+        return Err(
+            format!("Crop `{s}` is larger than the {}x{} source", inf.width, inf.height).into()
+        );
+    }
+
+    Ok(crop)
+}
+
+fn black_edges(frame: &[u8], width: usize, height: usize, thr: u32) -> Crop {
+    let luma = |x: usize, y: usize| {
+        let i = (y * width + x) * 2;
+        u32::from(u16::from_le_bytes([frame[i], frame[i + 1]]))
+    };
+    let row_black = |y: usize| (0..width).map(|x| luma(x, y)).sum::<u32>() <= thr * width as u32;
+    let col_black = |x: usize| (0..height).map(|y| luma(x, y)).sum::<u32>() <= thr * height as u32;
+
+    let top = (0..height).take_while(|&y| row_black(y)).count();
+    let bottom = (0..height).rev().take_while(|&y| row_black(y)).count();
+    let left = (0..width).take_while(|&x| col_black(x)).count();
+    let right = (0..width).rev().take_while(|&x| col_black(x)).count();
+
+    Crop { top: top as u32, bottom: bottom as u32, left: left as u32, right: right as u32 }
+}
+
+pub fn detect(
+    idx: &Arc<VidIdx>,
+    inf: &VidInf,
+    threads: usize,
+) -> Result<Crop, Box<dyn std::error::Error>> {
+    let source = thr_vid_src(idx, threads.try_into().unwrap_or(8))?;
+    let (width, height) = (inf.width as usize, inf.height as usize);
+    let thr = if inf.color_range == Some(1) { 32 } else { 96 };
+
+    let mut frame = vec![0u8; calc_10bit_size(inf)];
+    let mut found: Option<Crop> = None;
+    let samples = 36.min(inf.frames);
+
+    for i in 0..samples {
+        let pos = inf.frames * (2 * i + 1) / (2 * samples);
+        if extr_10bit(source, pos, &mut frame).is_err() {
+            continue;
+        }
+
+        let c = black_edges(&frame, width, height, thr);
+        if c.top as usize == height || c.left as usize == width {
+            continue;
+        }
+
+        found = Some(found.map_or(c, |f| Crop {
+            top: f.top.min(c.top),
+            bottom: f.bottom.min(c.bottom),
+            left: f.left.min(c.left),
+            right: f.right.min(c.right),
+        }));
+    }
+
+    destroy_vid_src(source);
+
+    let c = found.unwrap_or_default();
+    Ok(Crop { top: c.top & !1, bottom: c.bottom & !1, left: c.left & !1, right: c.right & !1 })
+}
@@ -0,0 +1,192 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::chunk::Chunk;
+use crate::error::XavError;
+use crate::tools::ShowCmd;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DynMeta {
+    Hdr10Plus,
+    DolbyVision,
+}
+
+impl DynMeta {
+    pub const fn tool(self) -> &'static str {
+        match self {
+            Self::Hdr10Plus => "hdr10plus_tool",
+            Self::DolbyVision => "dovi_tool",
+        }
+    }
+
+    pub const fn svt_flag(self) -> &'static str {
+        match self {
+            Self::Hdr10Plus => "--hdr10plus-json",
+            Self::DolbyVision => "--dolby-vision-rpu",
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Hdr10Plus => "HDR10+ metadata",
+            Self::DolbyVision => "Dolby Vision RPU",
+        }
+    }
+
+    const fn file(self) -> &'static str {
+        match self {
+            Self::Hdr10Plus => "hdr10plus",
+            Self::DolbyVision => "dovi",
+        }
+    }
+
+    const fn ext(self) -> &'static str {
+        match self {
+            Self::Hdr10Plus => "json",
+            Self::DolbyVision => "bin",
+        }
+    }
+
+    fn extract(self, input: &Path, output: &Path) -> Result<(), XavError> {
+        let mut cmd = Command::new(self.tool());
+        match self {
+            Self::Hdr10Plus => cmd.args(["-q", "extract", "-o"]),
+            Self::DolbyVision => match dv_profile(input) {
+                Some(5) => {
+                    return Err(XavError::Tool(
+                        "Dolby Vision profile 5 has no HDR10 base layer and cannot be carried \
+                         into AV1"
+                            .to_string(),
+                    ));
+                }
+                Some(7) => cmd.args(["-m", "2", "extract-rpu", "-o"]),
+                _ => cmd.args(["extract-rpu", "-o"]),
+            },
+        };
+
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-nostdin", "-i"])
+            .arg(input)
+            .args([
+                "-map",
+                "0:v:0",
+                "-c:v",
+                "copy",
+                "-bsf:v",
+                "hevc_mp4toannexb",
+                "-f",
+                "hevc",
+                "-",
+            ])
+            .stdout(Stdio::piped())
+            .show()
+            .spawn()
+            .map_err(|e| XavError::Tool(format!("Failed to run ffmpeg: {e}")))?;
+        let stdout = ffmpeg.stdout.take().unwrap();
+
+        let status = cmd
+            .arg(output)
+            .arg("-")
+            .stdin(stdout)
+            .stdout(Stdio::null())
+            .show()
+            .status()
+            .map_err(|e| XavError::Tool(format!("Failed to run {}: {e}", self.tool())))?;
+        let _ = ffmpeg.wait();
+
+        if !status.success() || !output.exists() {
+            return Err(XavError::Tool(format!(
+                "No {} could be extracted from {} (HEVC sources only)",
+                self.name(),
+                input.display()
+            )));
+        }
+        Ok(())
+    }
+
+    fn cut(self, src: &Path, edits: &Path, out: &Path) -> bool {
+        let mut cmd = Command::new(self.tool());
+        match self {
+            Self::Hdr10Plus => cmd.args(["-q", "editor"]).arg(src),
+            Self::DolbyVision => cmd.args(["editor", "-i"]).arg(src),
+        };
+        cmd.arg("-j")
+            .arg(edits)
+            .arg("-o")
+            .arg(out)
+            .stdout(Stdio::null())
+            .show()
+            .status()
+            .is_ok_and(|s| s.success())
+    }
+
+    pub fn prepare(
+        self,
+        input: &Path,
+        chunks: &[Chunk],
+        frames: usize,
+        work_dir: &Path,
+    ) -> Result<PathBuf, XavError> {
+        let src = work_dir.join(format!("{}.{}", self.file(), self.ext()));
+        if !src.exists() {
+            self.extract(input, &src)?;
+        }
+
+        let dir = work_dir.join(self.file());
+        fs::create_dir_all(&dir).map_err(|e| XavError::Encode(e.to_string()))?;
+
+        for chunk in chunks {
+            let out = self.chunk_file(&dir, chunk.idx);
+            if out.exists() {
+                continue;
+            }
+
+            let mut remove = Vec::new();
+            if chunk.start > 0 {
+                remove.push(format!("\"0-{}\"", chunk.start - 1));
+            }
+            if chunk.end < frames {
+                remove.push(format!("\"{}-{}\"", chunk.end, frames - 1));
+            }
+            let edits = dir.join(format!("{:04}_edits.json", chunk.idx));
+            fs::write(&edits, format!("{{\"remove\": [{}]}}", remove.join(", ")))
+                .map_err(|e| XavError::Encode(e.to_string()))?;
+
+            let ok = self.cut(&src, &edits, &out);
+            let _ = fs::remove_file(&edits);
+            if !ok {
+                return Err(XavError::Tool(format!(
+                    "{} could not cut the {} for chunk {:04}",
+                    self.tool(),
+                    self.name(),
+                    chunk.idx
+                )));
+            }
+        }
+        Ok(dir)
+    }
+
+    pub fn chunk_file(self, dir: &Path, idx: usize) -> PathBuf {
+        dir.join(format!("{idx:04}.{}", self.ext()))
+    }
+}
+
+fn dv_profile(input: &Path) -> Option<u8> {
+    let out = Command::new("ffprobe")
+        .args([
+            "-v",
+            "quiet",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream_side_data=dv_profile",
+            "-of",
+            "csv=p=0",
+        ])
+        .arg(input)
+        .show()
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&out.stdout).split([',', '\n']).find_map(|v| v.trim().parse().ok())
+}
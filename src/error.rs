@@ -0,0 +1,61 @@
+use std::fmt;
+
+pub const PANIC_CODE: i32 = 101;
+
+#[derive(Debug)]
+pub enum XavError {
+    Args(String),
+    Tool(String),
+    Source(String),
+    Encode(String),
+    Mux(String),
+    #[cfg(feature = "vship")]
+    Vship(String),
+    Scenes(String),
+    Space(String),
+    Interrupted,
+}
+
+impl XavError {
+    pub const fn code(&self) -> i32 {
+        match self {
+            Self::Args(_) => 2,
+            Self::Tool(_) => 3,
+            Self::Source(_) => 4,
+            Self::Encode(_) => 5,
+            Self::Mux(_) => 6,
+            #[cfg(feature = "vship")]
+            Self::Vship(_) => 7,
+            Self::Scenes(_) => 8,
+            Self::Space(_) => 9,
+            Self::Interrupted => 130,
+        }
+    }
+}
+
+impl fmt::Display for XavError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Args(msg)
+            | Self::Tool(msg)
+            | Self::Source(msg)
+            | Self::Encode(msg)
+            | Self::Mux(msg)
+            | Self::Scenes(msg)
+            | Self::Space(msg) => write!(f, "{msg}"),
+            #[cfg(feature = "vship")]
+            Self::Vship(msg) => write!(f, "{msg}"),
+            Self::Interrupted => write!(f, "Interrupted, finished chunks are kept for `-r`"),
+        }
+    }
+}
+
+impl std::error::Error for XavError {}
+
+pub fn code_of(err: &(dyn std::error::Error + 'static)) -> i32 {
+    err.downcast_ref::<XavError>().map_or(1, XavError::code)
+}
+
+pub fn classify(err: Box<dyn std::error::Error>, kind: fn(String) -> XavError) -> XavError {
+    err.downcast::<XavError>().map_or_else(|e| kind(e.to_string()), |e| *e)
+}
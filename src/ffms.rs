@@ -1,6 +1,6 @@
 use std::ffi::CString;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 #[repr(C)]
 pub struct FFMS_ErrorInfo {
@@ -52,7 +52,7 @@ pub struct FFMS_Frame {
     pub linesize: [i32; 4],
     pub encoded_width: i32,
     pub encoded_height: i32,
-    _encoded_pixel_format: i32,
+    encoded_pixel_format: i32,
     _scaled_width: i32,
     _scaled_height: i32,
     _converted_pixel_format: i32,
@@ -69,6 +69,20 @@ pub struct FFMS_Frame {
     pub chroma_location: i32,
 }
 
+#[repr(C)]
+struct FFMS_FrameInfo {
+    pts: i64,
+    _repeat_pict: i32,
+    _key_frame: i32,
+    _original_pts: i64,
+}
+
+#[repr(C)]
+struct FFMS_TrackTimeBase {
+    num: i64,
+    den: i64,
+}
+
 type IndexCallback = extern "C" fn(current: i64, tot: i64, ic_private: *mut libc::c_void) -> i32;
 
 unsafe extern "C" {
@@ -100,6 +114,10 @@ unsafe extern "C" {
     fn FFMS_GetVideoProperties(v: *mut libc::c_void) -> *const FFMS_VideoProperties;
     fn FFMS_GetFrame(v: *mut libc::c_void, n: i32, err: *mut FFMS_ErrorInfo) -> *const FFMS_Frame;
     fn FFMS_DestroyVideoSource(v: *mut libc::c_void);
+    fn FFMS_GetTrackFromVideo(v: *mut libc::c_void) -> *mut libc::c_void;
+    fn FFMS_GetTimeBase(t: *mut libc::c_void) -> *const FFMS_TrackTimeBase;
+    fn FFMS_GetFrameInfo(t: *mut libc::c_void, frame: i32) -> *const FFMS_FrameInfo;
+    fn FFMS_GetPixFmt(name: *const i8) -> i32;
     fn FFMS_DestroyIndex(idx: *mut libc::c_void);
     fn FFMS_WriteIndex(
         idx_file: *const i8,
@@ -124,11 +142,38 @@ pub struct VidInf {
     pub color_primaries: Option<i32>,
     pub transfer_characteristics: Option<i32>,
     pub matrix_coefficients: Option<i32>,
-    pub is_10bit: bool,
+    pub bit_depth: u8,
     pub color_range: Option<i32>,
     pub chroma_sample_position: Option<i32>,
     pub mastering_display: Option<String>,
     pub content_light: Option<String>,
+    pub dyn_meta: Vec<(crate::dynmeta::DynMeta, std::path::PathBuf)>,
+    pub out_8bit: bool,
+    pub rc_qp: bool,
+}
+
+impl VidInf {
+    pub const fn is_10bit(&self) -> bool {
+        self.bit_depth > 8
+    }
+}
+
+fn yuv420_depth(pix_fmt: i32) -> Option<u8> {
+    static FMTS: OnceLock<[(i32, u8); 4]> = OnceLock::new();
+    let fmts = FMTS.get_or_init(|| unsafe {
+        [
+            (FFMS_GetPixFmt(c"yuv420p".as_ptr()), 8),
+            (FFMS_GetPixFmt(c"yuvj420p".as_ptr()), 8),
+            (FFMS_GetPixFmt(c"yuv420p10le".as_ptr()), 10),
+            (FFMS_GetPixFmt(c"yuv420p12le".as_ptr()), 12),
+        ]
+    });
+    fmts.iter().find(|&&(f, _)| f == pix_fmt).map(|&(_, depth)| depth)
+}
+
+fn is_12bit(pix_fmt: i32) -> bool {
+    static YUV420P12: OnceLock<i32> = OnceLock::new();
+    *YUV420P12.get_or_init(|| unsafe { FFMS_GetPixFmt(c"yuv420p12le".as_ptr()) }) == pix_fmt
 }
 
 pub struct VidIdx {
@@ -284,8 +329,13 @@ pub fn get_vidinf(idx: &Arc<VidIdx>) -> Result<VidInf, Box<dyn std::error::Error
 
         let width = (*frame).encoded_width as u32;
         let height = (*frame).encoded_height as u32;
-        let y_linesize = (*frame).linesize[0] as usize;
-        let is_10bit = y_linesize >= (width as usize) * 2;
+        let Some(bit_depth) = yuv420_depth((*frame).encoded_pixel_format) else {
+            FFMS_DestroyVideoSource(video);
+            return Err("Unsupported pixel format, only 4:2:0 YUV at 8, 10 or 12 bits is \
+                        supported. Convert RGB or 4:2:2/4:4:4 sources first, e.g. with `ffmpeg \
+                        -pix_fmt yuv420p10le`"
+                .into());
+        };
 
         let color_range = match (*frame).color_range {
             1 => Some(0),
@@ -334,11 +384,14 @@ pub fn get_vidinf(idx: &Arc<VidIdx>) -> Result<VidInf, Box<dyn std::error::Error
             color_primaries: Some((*frame).color_primaries),
             transfer_characteristics: Some((*frame).transfer_characteristics),
             matrix_coefficients: Some(matrix_coeff),
-            is_10bit,
+            bit_depth,
             color_range,
             chroma_sample_position,
             mastering_display,
             content_light,
+            dyn_meta: Vec::new(),
+            out_8bit: false,
+            rc_qp: false,
         };
 
         FFMS_DestroyVideoSource(video);
@@ -372,6 +425,31 @@ pub fn thr_vid_src(
     }
 }
 
+pub fn vfr_avg_fps(idx: &Arc<VidIdx>) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    let video = thr_vid_src(idx, 1)?;
+    let (pts, tb) = unsafe {
+        let track = FFMS_GetTrackFromVideo(video);
+        let tb = &*FFMS_GetTimeBase(track);
+        let frames = (*FFMS_GetVideoProperties(video)).num_frames;
+        let pts: Vec<i64> = (0..frames).map(|i| (*FFMS_GetFrameInfo(track, i)).pts).collect();
+        (pts, tb.num as f64 / tb.den as f64)
+    };
+    destroy_vid_src(video);
+
+    if pts.len() < 3 {
+        return Ok(None);
+    }
+    let span = (pts[pts.len() - 1] - pts[0]) as f64;
+    let mean = span / (pts.len() - 1) as f64;
+    let tol = (mean * 0.1).max(1.0);
+    let off = pts.windows(2).filter(|w| ((w[1] - w[0]) as f64 - mean).abs() > tol).count();
+    if off * 100 <= pts.len() {
+        return Ok(None);
+    }
+
+    Ok(Some((pts.len() - 1) as f64 * 1000.0 / (span * tb)))
+}
+
 pub const fn calc_8bit_size(inf: &VidInf) -> usize {
     (inf.width * inf.height * 3 / 2) as usize
 }
@@ -398,8 +476,8 @@ pub fn extr_8bit(
             return Err("Failed to get frame".into());
         }
 
-        let width = (*frame).encoded_width as usize;
-        let height = (*frame).encoded_height as usize;
+        let width = (*frame).encoded_width as usize & !1;
+        let height = (*frame).encoded_height as usize & !1;
         let y_linesize = (*frame).linesize[0] as usize;
         let mut pos = 0;
 
@@ -438,6 +516,13 @@ pub fn conv_to_10bit(input: &[u8], output: &mut [u8]) {
     });
 }
 
+pub fn conv_to_8bit(frame: &mut [u8]) {
+    for i in 0..frame.len() / 2 {
+        let pixel = u16::from_le_bytes([frame[i * 2], frame[i * 2 + 1]]);
+        frame[i] = ((pixel + 2) >> 2).min(255) as u8;
+    }
+}
+
 #[inline]
 pub fn pack_4_pix_10bit(input: [u8; 8], output: &mut [u8; 5]) {
     let p0 = u64::from(u16::from_le_bytes([input[0], input[1]]));
@@ -540,6 +625,33 @@ fn copy_plane_10to10(
     }
 }
 
+fn copy_plane_12to10(
+    src: *const u8,
+    src_linesize: usize,
+    width: usize,
+    height: usize,
+    output: &mut [u8],
+    out_pos: &mut usize,
+) {
+    unsafe {
+        for row in 0..height {
+            let src_row = std::slice::from_raw_parts(src.add(row * src_linesize), width * 2);
+            let out_start = *out_pos;
+            let out_end = out_start + width * 2;
+
+            src_row.chunks_exact(2).zip(output[out_start..out_end].chunks_exact_mut(2)).for_each(
+                |(pixel, out_chunk)| {
+                    let pixel_12bit = u16::from_le_bytes([pixel[0], pixel[1]]);
+                    let pixel_10bit = ((pixel_12bit + 2) >> 2).min(1023).to_le_bytes();
+                    out_chunk.copy_from_slice(&pixel_10bit);
+                },
+            );
+
+            *out_pos = out_end;
+        }
+    }
+}
+
 pub fn extr_10bit(
     vid_src: *mut libc::c_void,
     frame_idx: usize,
@@ -557,15 +669,21 @@ pub fn extr_10bit(
             return Err("Failed to get frame".into());
         }
 
-        let width = (*frame).encoded_width as usize;
-        let height = (*frame).encoded_height as usize;
+        let width = (*frame).encoded_width as usize & !1;
+        let height = (*frame).encoded_height as usize & !1;
 
         if width == 0 || height == 0 {
             return Err("Invalid frame dimensions".into());
         }
 
         let y_linesize = (*frame).linesize[0] as usize;
-        let is_10bit = y_linesize >= width * 2;
+        let copy_plane = if is_12bit((*frame).encoded_pixel_format) {
+            copy_plane_12to10
+        } else if y_linesize >= width * 2 {
+            copy_plane_10to10
+        } else {
+            copy_plane_8to10
+        };
         let mut out_pos = 0;
 
         let y_ptr = (*frame).data[0];
@@ -573,11 +691,7 @@ pub fn extr_10bit(
             return Err("Null Y plane pointer".into());
         }
 
-        if is_10bit {
-            copy_plane_10to10(y_ptr, y_linesize, width, height, output, &mut out_pos);
-        } else {
-            copy_plane_8to10(y_ptr, y_linesize, width, height, output, &mut out_pos);
-        }
+        copy_plane(y_ptr, y_linesize, width, height, output, &mut out_pos);
 
         let uv_width = width / 2;
         let uv_height = height / 2;
@@ -586,22 +700,14 @@ pub fn extr_10bit(
         let u_linesize = (*frame).linesize[1] as usize;
 
         if !u_ptr.is_null() {
-            if is_10bit {
-                copy_plane_10to10(u_ptr, u_linesize, uv_width, uv_height, output, &mut out_pos);
-            } else {
-                copy_plane_8to10(u_ptr, u_linesize, uv_width, uv_height, output, &mut out_pos);
-            }
+            copy_plane(u_ptr, u_linesize, uv_width, uv_height, output, &mut out_pos);
         }
 
         let v_ptr = (*frame).data[2];
         let v_linesize = (*frame).linesize[2] as usize;
 
         if !v_ptr.is_null() {
-            if is_10bit {
-                copy_plane_10to10(v_ptr, v_linesize, uv_width, uv_height, output, &mut out_pos);
-            } else {
-                copy_plane_8to10(v_ptr, v_linesize, uv_width, uv_height, output, &mut out_pos);
-            }
+            copy_plane(v_ptr, v_linesize, uv_width, uv_height, output, &mut out_pos);
         }
 
         Ok(())
@@ -1,22 +1,33 @@
 use std::collections::hash_map::DefaultHasher;
 use std::fs;
 use std::hash::{Hash, Hasher};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::XavError;
 
 mod audio;
 mod chunk;
+mod crop;
+mod dynmeta;
+mod error;
 mod ffms;
 #[cfg(feature = "vship")]
 mod interp;
 mod noise;
 mod progs;
 mod scd;
+#[cfg(feature = "vship")]
+mod ssimu2;
 mod svt;
+mod tools;
 #[cfg(feature = "vship")]
 mod tq;
 #[cfg(feature = "vship")]
 mod vship;
+#[cfg(feature = "vship")]
+mod xpsnr;
 
 const G: &str = "\x1b[1;92m";
 const R: &str = "\x1b[1;91m";
@@ -27,32 +38,116 @@ const C: &str = "\x1b[1;96m";
 const W: &str = "\x1b[1;97m";
 const N: &str = "\x1b[0m";
 
+pub static PANICKED: AtomicBool = AtomicBool::new(false);
+pub static ENCODING: AtomicBool = AtomicBool::new(false);
+pub static STOP: AtomicBool = AtomicBool::new(false);
+pub static PRINT_CMDS: AtomicBool = AtomicBool::new(false);
+static TUI: AtomicBool = AtomicBool::new(false);
+static SPOOL: std::sync::OnceLock<(PathBuf, bool)> = std::sync::OnceLock::new();
+
 #[derive(Clone)]
 pub struct Args {
     pub worker: usize,
+    pub threads: usize,
     pub scene_file: PathBuf,
+    pub scd_fast: bool,
+    pub scd_overlap: bool,
+    pub no_scd: bool,
+    pub chunk_frames: Option<usize>,
+    pub scene_len: (Option<chunk::SceneLen>, Option<chunk::SceneLen>),
     #[cfg(feature = "vship")]
     pub target_quality: Option<String>,
     #[cfg(feature = "vship")]
     pub metric_mode: String,
     #[cfg(feature = "vship")]
     pub qp_range: Option<String>,
+    #[cfg(feature = "vship")]
+    pub max_rounds: usize,
+    #[cfg(feature = "vship")]
+    pub probe_log: Option<PathBuf>,
+    #[cfg(feature = "vship")]
+    pub gpu: Option<Vec<i32>>,
+    #[cfg(feature = "vship")]
+    pub xpsnr: bool,
+    #[cfg(feature = "vship")]
+    pub probe_preset: Option<u8>,
+    #[cfg(feature = "vship")]
+    pub crf_step: f64,
+    #[cfg(feature = "vship")]
+    pub metric_res: Option<u32>,
+    #[cfg(feature = "vship")]
+    pub metric_stride: usize,
+    #[cfg(feature = "vship")]
+    pub metric_workers: usize,
+    #[cfg(feature = "vship")]
+    pub guard: Option<tq::Guard>,
     pub params: String,
+    pub rc_qp: bool,
+    pub target_size: Option<f64>,
+    pub two_pass: bool,
+    pub sequential: bool,
+    pub stream: bool,
+    pub output_depth: u8,
+    pub pin: bool,
+    pub split: Option<usize>,
+    pub trim: Option<(usize, usize)>,
+    pub sample: Option<usize>,
+    pub fps: Option<(u32, u32)>,
+    pub merge: bool,
     pub resume: bool,
     pub quiet: bool,
+    pub json: bool,
+    pub embed_settings: bool,
+    pub dry_run: bool,
+    pub chapters: bool,
+    pub keep_workdir: bool,
+    pub overwrite: bool,
+    pub keep_meta: bool,
+    pub dyn_meta: Vec<dynmeta::DynMeta>,
+    pub verify: bool,
+    pub print_command: bool,
+    pub log: Option<PathBuf>,
+    pub progress_socket: Option<PathBuf>,
+    pub work_root: Option<PathBuf>,
+    pub subs: Option<Vec<String>>,
+    pub pool: usize,
+    pub mem: Option<usize>,
     pub noise: Option<u32>,
-    pub crop: Option<(u32, u32)>,
+    pub seed: Option<u64>,
+    pub grain_table: Option<PathBuf>,
+    pub crop: Option<crop::Crop>,
     pub crop_str: Option<String>,
     pub audio: Option<audio::AudioSpec>,
+    pub audio_only: Option<PathBuf>,
     pub input: PathBuf,
+    pub stdin: bool,
     pub output: PathBuf,
+    pub name: Option<String>,
 }
 
 extern "C" fn restore() {
+    if !TUI.load(Ordering::Relaxed) {
+        return;
+    }
     print!("\x1b[?25h\x1b[?1049l");
     let _ = std::io::stdout().flush();
 }
-extern "C" fn exit_restore(_: i32) {
+extern "C" fn remove_spool() {
+    let Some((work_dir, keep)) = SPOOL.get() else { return };
+    if *keep {
+        let _ = fs::remove_file(work_dir.join("stdin.y4m"));
+        let _ = fs::remove_file(work_dir.join("stdin.y4m.ffidx"));
+    } else {
+        let _ = fs::remove_dir_all(work_dir);
+    }
+}
+extern "C" fn exit_restore(sig: i32) {
+    if sig == libc::SIGINT && ENCODING.load(Ordering::Relaxed) && !STOP.swap(true, Ordering::SeqCst)
+    {
+        let msg = b"\nFinishing the current chunks, press Ctrl-C again to abort\n";
+        unsafe { libc::write(2, msg.as_ptr().cast(), msg.len()) };
+        return;
+    }
     restore();
     std::process::exit(130);
 }
@@ -61,12 +156,39 @@ extern "C" fn exit_restore(_: i32) {
 fn print_help() {
     println!("Format: xav [options] <INPUT> [<OUTPUT>]");
     println!();
-    println!("<INPUT>        Input path");
+    println!("<INPUT>        Input path. `-` reads a y4m stream from stdin, <OUTPUT> is then required");
+    println!("               The stream is buffered in the work dir, which is removed when xav exits");
     println!("<OUTPUT>       Output path. Adds `_av1` to the input name if not specified");
+    println!("               `.webm` extension writes WebM (Opus audio only, no subtitles/chapters)");
+    println!("               `.mp4` extension muxes with ffmpeg (no subtitles/chapters/tags)");
+    println!("               `.avif` extension writes AVIF from an image or clip as one chunk");
+    println!("               The source must be 4:2:0 YUV, convert RGB images (PNG) first");
+    println!("--name         Output name template when <OUTPUT> is not given, relative to the input");
+    println!("               Fields: {{stem}} {{preset}} {{crf}} {{width}} {{height}} {{date}}");
+    println!("               Example: `--name \"{{stem}}/{{stem}}.p{{preset}}.mkv\"`");
     println!();
     println!("Options:");
     println!("-p|--param     SVT AV1 parameters inside quotes");
+    println!("--preset       SVT preset [-1-13]. Overrides `--preset` in `-p`");
+    println!("--lp           SVT logical processors per worker. Overrides `--lp` in `-p`");
+    println!("--tune         SVT tune [0-4]. Overrides `--tune` in `-p`");
+    println!("--rc           Rate control: `crf` (default) or `qp` for constant QP. `qp` sets");
+    println!("               `--aq-mode 0` and passes whole `--qp` values, also for `-t` / `-f`");
+    println!("--profile      Tune, sharpness and variance boost for `film`, `anime`, `grain` or");
+    println!("               `screen` content. Params given in `-p` take precedence");
     println!("-w|--worker    Number of `svt-av1` instances to run");
+    println!("--threads      Cap decoder and metric threads. Default: all cores");
+    println!("--pin          Pin each worker and its encoder to its own share of the CPUs xav may run on");
+    println!("--output-depth Encode depth, `8` or `10` (default). `8` feeds 8-bit sources straight");
+    println!("               through and rounds 10/12-bit sources down to 8-bit. Not for TQ");
+    println!("--two-pass     Run SVT first pass per chunk for stats, then the final pass. Not for TQ");
+    println!("--target-size  Video size budget in MB. Probes sample chunks for one CRF that fits it");
+    println!("--sequential   Encode chunks in file order instead of longest first");
+    println!("--pool         Number of chunk buffers to recycle. Default: workers + 1");
+    println!("--stream       Each worker decodes its own chunk through a few-frame ring. Not for TQ");
+    println!("               Keeps RAM per worker at a few frames instead of a whole chunk");
+    println!("--mem          Cap decoded chunks waiting for or held by workers, in GB: `--mem 6`");
+    println!("               The decoder waits for a free slot. A chunk larger than this runs alone");
     println!();
     #[cfg(feature = "vship")]
     {
@@ -75,36 +197,209 @@ fn print_help() {
         println!("               SSIMU2: `74.00-76.00`, Butter: `1.5-2.0`, CVVDP: `9.45-9.55`");
         println!("-m|--mode      Metric evaluation: `mean` or `pN` for mean of worst N%. Example: `p15`");
         println!("-f|--qp        CRF/QP search range. Example: `12.25-44.75`");
+        println!("--xpsnr        Score probes with CPU XPSNR in dB instead: `-t 40.5-41.5`");
+        println!("--crf-step     CRF granularity of the search: `0.25`, `0.1` or `0.05`. Default: 0.25");
+        println!("--probe-preset Faster SVT preset for probes. The chosen CRF is re-encoded with `-p`");
+        println!("--max-rounds   Maximum probes per chunk before picking the closest. Default: 10");
+        println!("--gpu          GPU ids for metric workers, round-robin: `0,1`. Default: all devices");
+        println!("--metric-res   Downscale to this height for GPU metrics: `1080`. Much faster on 4K");
+        println!("               Scores shift at lower resolutions, so `-t` may need recalibrating");
+        println!("--metric-stride");
+        println!("               Score only every Nth frame with SSIMU2 / Butter5pn: `--metric-stride 2`");
+        println!("               About N times faster metrics, but short artifacts can slip through");
+        println!("--metric-workers");
+        println!("               Max probes scored at once, for a slow GPU with many `-w`. Default: `-w`");
+        println!("--guard        Also require a second metric per probe: `ssimu2:70` = SSIMU2 at least 70,");
+        println!("               `butter:2.5` = Butter5pn at most 2.5. Pooled with `-m` like the target");
+        println!("--probe-log    Write every chunk's probes to a CSV, or JSON if the path ends in `.json`");
         println!();
     }
     println!("Misc:");
     println!("-n|--noise     Apply photon noise [1-64]: 1=ISO100, 64=ISO6400");
+    println!("--seed         Fixed photon noise seed for reproducible grain tables. Needs `-n`");
+    println!("--grain-table  Apply a prebuilt film grain table file instead. Not with `-n`");
+    println!("--film-grain   SVT film grain synthesis level [1-50]. Not with `-n`, `--grain-table`");
+    println!("               or `--denoise`, which all set the grain on their own");
+    println!("--denoise      Denoise with SVT before grain synthesis [1-50]. Sets `--film-grain`");
+    println!("               and `--film-grain-denoise 1`. A `-n` or `--grain-table` table replaces");
+    println!("               the estimated grain. Slows encoding down noticeably");
+    println!("--hdr10plus    Carry HDR10+ metadata of HEVC sources into each chunk. Needs `hdr10plus_tool`");
+    println!("               and an SVT-AV1 build with `--hdr10plus-json`");
+    println!("--dovi         Carry the Dolby Vision RPU of HEVC sources into each chunk. Needs `dovi_tool`");
+    println!("               and an SVT-AV1 build with `--dolby-vision-rpu`. The RPU is in the AV1 stream,");
+    println!("               the MKV track gets no Dolby Vision configuration record (see README).");
+    println!("               Profile 7 RPUs are converted to 8.1, profile 5 sources are rejected");
     println!("-c|--crop      Auto crop by original AR: `1.37` OR crop horizontal,vertical: `0,220`");
+    println!("               OR per side top,bottom,left,right: `140,144,0,0` OR detect black bars: `auto`");
     println!("-s|--sc        SCD file to use. Runs SCD and creates the file if not specified");
-    println!("-a|--audio     Encode with Opus: `-a \"<auto|norm|bitrate> <all|stream_ids>\"`");
+    println!("               Also reads x264/x265 qpfiles and ffmpeg `showinfo` / `metadata=print` dumps");
+    println!("               Cuts are written to `<file>.part` as they are found. After an interrupted");
+    println!("               SCD, `--scd-overlap` encodes those scenes while detection restarts");
+    println!("--no-scd       Skip SCD and encode the whole file as one chunk. Not with `-s`");
+    println!("               Implies `--stream`, so the chunk is never buffered in RAM");
+    println!("--chunk-frames Skip SCD and cut chunks of exactly N frames, the last one shorter");
+    println!("--scd-overlap  Start encoding while SCD runs, in file order. Only if the SCD file is new");
+    println!("--scd-speed    `standard` (default) or `fast`. Fast may place cuts slightly differently");
+    println!("--min-scene    Minimum scene length in frames, or seconds with `s`: `12` or `0.5s`");
+    println!("               Default: 1s");
+    println!("--max-scene    Maximum scene length in frames or seconds: `480` or `20s`");
+    println!("               Default: 10s, at most 300 frames");
+    println!("--merge        Merge scenes shorter than 1s into their neighbours (up to the 10s limit)");
+    println!("--trim         Encode only frames start:end (end exclusive): `--trim 1000:2000`");
+    println!("--sample       Encode only N chunks spread evenly over the video into `<stem>_sample.mkv`");
+    println!("               to check size and quality of `-p` / `-t` before a full run: `--sample 5`");
+    println!("               The input size in the stats is scaled to the sampled frames");
+    println!("--fps          Override a mislabeled source frame rate: `--fps 24000/1001` or `--fps 25`");
+    println!("--split        Split scenes longer than N frames into even sub-chunks: `--split 240`");
+    println!("-a|--audio     Encode with Opus: `-a \"<auto|norm|bitrate> <all|stream_ids|languages>\"`");
     println!("               Examples: `-a \"auto all\"`, `-a \"norm 1\"`, `-a \"128 1,2,3\"`");
+    println!("               By language: `-a \"auto eng,jpn\"`");
+    println!("               Per stream: `-a \"128,96 1,2\"` = stream 1 at 128k, stream 2 at 96k");
     println!("               `norm`: downmix to stereo + loudnorm + 128k bitrate");
+    println!("               `norm:I:TP:LRA` sets loudnorm targets. Default: `norm:-14:-2.5:14`");
+    println!("               `norm:music` / `norm:dialog` pick another downmix than `film`");
     println!("               If enabled, subtitles/chapters are preserved in output");
+    println!("--audio-delay  Shift encoded audio in ms, negative when audio leads: `--audio-delay 120`");
+    println!("               Per stream: `--audio-delay 1:120,2:-40`");
+    println!("--audio-only   Skip the video encode and mux `-a` audio into this finished video");
+    println!("               `xav --audio-only i_av1.mkv -a \"auto all\" i.mkv` replaces its audio");
+    println!("--subs         Keep only subtitles in these languages with `-a`: `eng,jpn`");
+    println!("--chapters     Copy chapters from the input, also without `-a`");
+    println!("--work-dir     Create the `.<hash>` work dir in this directory instead of next to");
+    println!("               the input. Falls back to `$TMPDIR` if set. Pass it again with `-r`");
+    println!("               `-r` still finds a work dir next to the input when `$TMPDIR` is set");
+    println!("--keep-workdir Keep chunks, probes and the merged video after a successful encode");
+    println!("--overwrite    Replace <OUTPUT> if it already exists. Resume runs may always replace it");
+    println!("--keep-meta    Copy the SCD file, grain table and commands to `.xav-meta/<name>/`");
+    println!("               next to the output, to re-encode the same way later");
+    println!("--verify       Decode the finished output and check its frame count and resolution");
+    println!("--print-command");
+    println!("               Print every SVT, ffmpeg and mkvmerge command to stderr before it runs");
+    println!("               Best with `-q`, the progress display hides them otherwise");
+    println!("--dry-run      Print chunks, the SVT command and audio streams, then exit");
     println!("-r|--resume    Resume the encoding. Example below");
+    println!("--resume-list  List the resumable encodes in a directory with their progress");
+    println!("               `xav --resume-list [dir]`, add `--json` for JSON. Default dir: `.`");
+    println!("--embed-settings");
+    println!("               Store xav version and encoding settings as global tags in output");
     println!("-q|--quiet     Do not run any code related to any progress");
+    println!("--log          Append plain progress lines to a file every 10s, also with `-q`");
+    println!("--json         Print the final stats as JSON to stdout. Implies `-q`");
+    println!("--progress-socket");
+    println!("               Serve newline-delimited JSON progress events on a Unix socket, also with `-q`");
     println!();
     println!("Examples:");
     println!("xav -r i.mkv");
-    println!("xav -w 8 -s sc.txt -p \"--lp 3 --tune 0\" i.mkv o.mkv");
+    println!("xav -w 8 -s sc.txt --lp 3 --tune 0 i.mkv o.mkv");
     println!("xav -q -w 8 -s sc.txt -t 75-76 -f 6-63 -m p15 -p \"--lp 3 --tune 0\" i.mkv o.mkv");
     println!("xav -t 1.5-2.0 -f 20-50 -m mean i.mkv  # Butteraugli target");
     println!("xav i.mkv  # Uses all defaults, creates `i_scd.txt` and output will be `i_av1.mkv`");
 }
 
+fn resume_list(dir: &Path, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rows = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.len() != 8 || !name[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        let work_dir = entry.path();
+        let Ok(cmd_line) = fs::read_to_string(work_dir.join("cmd.txt")) else { continue };
+        let Ok(args) = get_args(&parse_quoted_args(&cmd_line)) else { continue };
+        let base = fs::read_to_string(work_dir.join("cwd.txt")).map_or_else(
+            |_| {
+                let depth = args.input.parent().map_or(0, |p| p.components().count());
+                work_dir.parent().and_then(|p| p.ancestors().nth(depth)).map(Path::to_path_buf)
+            },
+            |cwd| Some(PathBuf::from(cwd)),
+        );
+        let Some(base) = base.and_then(|b| std::path::absolute(b).ok()) else { continue };
+        let resolve = |p: &Path| if p.is_absolute() { p.to_path_buf() } else { base.join(p) };
+
+        let done = chunk::get_resume(&work_dir).map_or(0, |r| r.chnks_done.len());
+        let total = (!args.no_scd && args.split.is_none() && !args.merge && args.trim.is_none())
+            .then(|| chunk::load_scenes(&resolve(&args.scene_file), usize::MAX, 1.0).ok())
+            .flatten()
+            .map(|s| s.len());
+
+        let quote = |s: String| if s.contains(' ') { format!("\"{s}\"") } else { s };
+        let mut resume = format!(
+            "cd {} && xav -r {}",
+            quote(base.display().to_string()),
+            quote(args.input.display().to_string())
+        );
+        let input = resolve(&args.input);
+        let root = work_dir.parent().and_then(|p| std::path::absolute(p).ok());
+        let beside_input = root.as_deref() == input.parent()
+            && work_dir.file_name().is_some_and(|n| {
+                n.to_string_lossy().strip_prefix('.') == Some(&hash_input(&args.input)[..7])
+            });
+        if !beside_input && let Some(root) = root {
+            resume.push_str(&format!(" --work-dir {}", quote(root.display().to_string())));
+        }
+        let input = input.display().to_string();
+        rows.push((input, work_dir, done, total, resume));
+    }
+    rows.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    if json {
+        let items: Vec<String> = rows
+            .iter()
+            .map(|(input, work_dir, done, total, resume)| {
+                format!(
+                    r#"{{"input":"{}","work_dir":"{}","chunks_done":{done},"chunks_total":{},"resume":"{}"}}"#,
+                    json_str(input),
+                    json_str(&work_dir.to_string_lossy()),
+                    total.map_or_else(|| "null".into(), |t| t.to_string()),
+                    json_str(resume)
+                )
+            })
+            .collect();
+        println!("[{}]", items.join(","));
+        return Ok(());
+    }
+
+    if rows.is_empty() {
+        eprintln!("No resumable encodes in {}", dir.display());
+    }
+    for (input, work_dir, done, total, resume) in &rows {
+        let total = total.map_or_else(|| "?".into(), |t| t.to_string());
+        println!("{W}{input} {C}({}){N}", work_dir.display());
+        println!("  {G}{done}/{total} {W}chunks done {Y}{resume}{N}");
+    }
+    Ok(())
+}
+
 fn parse_args() -> Args {
     let args: Vec<String> = std::env::args().collect();
-    get_args(&args).unwrap_or_else(|_| {
+    get_args(&args).unwrap_or_else(|e| {
+        eprintln!("{e}\n");
         print_help();
-        std::process::exit(1);
+        std::process::exit(XavError::Args(e.to_string()).code());
     })
 }
 
+fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
 fn apply_defaults(args: &mut Args) {
+    if args.threads == 0 {
+        args.threads = std::thread::available_parallelism().map_or(8, std::num::NonZero::get);
+    }
+
     if args.worker == 0 {
         let threads = std::thread::available_parallelism().map_or(8, std::num::NonZero::get);
         args.worker = match threads {
@@ -115,12 +410,31 @@ fn apply_defaults(args: &mut Args) {
             8..12 => 2,
             _ => 1,
         };
-        args.params = format!("--lp 3 {}", args.params).trim().to_string();
+        if !args.params.split_whitespace().any(|p| p == "--lp") {
+            args.params = format!("--lp 3 {}", args.params).trim().to_string();
+        }
+    }
+
+    if args.pool == 0 {
+        args.pool = args.worker + 1;
     }
 
     if args.output == PathBuf::new() {
         let stem = args.input.file_stem().unwrap().to_string_lossy();
-        args.output = args.input.with_file_name(format!("{stem}_av1.mkv"));
+        args.output = if let Some(ref name) = args.name {
+            let crf = svt::get_param(&args.params, svt::rate_key(args.rc_qp));
+            #[cfg(feature = "vship")]
+            let crf = crf.or(args.target_quality.as_ref().map(|_| "tq"));
+            args.input.with_file_name(
+                name.replace("{stem}", &stem)
+                    .replace("{preset}", svt::get_param(&args.params, "--preset").unwrap_or("def"))
+                    .replace("{crf}", crf.unwrap_or("def"))
+                    .replace("{date}", &today()),
+            )
+        } else {
+            let suffix = if args.sample.is_some() { "sample" } else { "av1" };
+            args.input.with_file_name(format!("{stem}_{suffix}.mkv"))
+        };
     }
 
     if args.scene_file == PathBuf::new() {
@@ -128,6 +442,11 @@ fn apply_defaults(args: &mut Args) {
         args.scene_file = args.input.with_file_name(format!("{stem}_scd.txt"));
     }
 
+    #[cfg(feature = "vship")]
+    if args.metric_workers == 0 || args.metric_workers > args.worker {
+        args.metric_workers = args.worker;
+    }
+
     #[cfg(feature = "vship")]
     if args.target_quality.is_some() && args.qp_range.is_none() {
         args.qp_range = Some("8.0-48.0".to_string());
@@ -140,22 +459,88 @@ fn get_args(args: &[String]) -> Result<Args, Box<dyn std::error::Error>> {
     }
 
     let mut worker = 0;
+    let mut threads = 0;
     let mut scene_file = PathBuf::new();
+    let mut scd_fast = false;
+    let mut scd_overlap = false;
+    let mut no_scd = false;
+    let mut chunk_frames = None;
+    let mut scene_len = (None, None);
     #[cfg(feature = "vship")]
     let mut target_quality = None;
     #[cfg(feature = "vship")]
     let mut metric_mode = "mean".to_string();
     #[cfg(feature = "vship")]
     let mut qp_range = None;
+    #[cfg(feature = "vship")]
+    let mut max_rounds = 10;
+    #[cfg(feature = "vship")]
+    let mut probe_log = None;
+    #[cfg(feature = "vship")]
+    let mut gpu = None;
+    #[cfg(feature = "vship")]
+    let mut xpsnr = false;
+    #[cfg(feature = "vship")]
+    let mut probe_preset = None;
+    #[cfg(feature = "vship")]
+    let mut crf_step = 0.25;
+    #[cfg(feature = "vship")]
+    let mut metric_res = None;
+    #[cfg(feature = "vship")]
+    let mut metric_stride = 1;
+    #[cfg(feature = "vship")]
+    let mut metric_workers = 0;
+    #[cfg(feature = "vship")]
+    let mut guard = None;
     let mut params = String::new();
+    let mut preset: Option<i8> = None;
+    let mut lp: Option<u32> = None;
+    let mut tune: Option<u8> = None;
+    let mut rc_qp = false;
+    let mut profile = None;
+    let mut denoise: Option<u8> = None;
+    let mut film_grain: Option<u8> = None;
+    let mut target_size = None;
+    let mut two_pass = false;
+    let mut sequential = false;
+    let mut pin = false;
+    let mut stream = false;
+    let mut output_depth = 10;
+    let mut split = None;
+    let mut trim = None;
+    let mut sample = None;
+    let mut fps = None;
+    let mut merge = false;
     let mut resume = false;
     let mut quiet = false;
+    let mut json = false;
+    let mut embed_settings = false;
+    let mut dry_run = false;
+    let mut chapters = false;
+    let mut keep_workdir = false;
+    let mut overwrite = false;
+    let mut keep_meta = false;
+    let mut dyn_meta = Vec::new();
+    let mut verify = false;
+    let mut print_command = false;
+    let mut log = None;
+    let mut progress_socket = None;
+    let mut work_root = None;
+    let mut subs = None;
+    let mut pool = 0;
+    let mut mem = None;
     let mut noise = None;
+    let mut seed = None;
+    let mut grain_table = None;
     let crop = None;
     let mut crop_str = None;
     let mut audio = None;
+    let mut audio_delay = None;
+    let mut audio_only = None;
     let mut input = PathBuf::new();
+    let mut stdin = false;
     let mut output = PathBuf::new();
+    let mut name = None;
 
     let mut i = 1;
     while i < args.len() {
@@ -166,12 +551,57 @@ fn get_args(args: &[String]) -> Result<Args, Box<dyn std::error::Error>> {
                     worker = args[i].parse()?;
                 }
             }
+            "--threads" => {
+                i += 1;
+                if i < args.len() {
+                    threads = args[i].parse()?;
+                }
+            }
             "-s" | "--sc" => {
                 i += 1;
                 if i < args.len() {
                     scene_file = PathBuf::from(&args[i]);
                 }
             }
+            "--scd-speed" => {
+                i += 1;
+                if i < args.len() {
+                    scd_fast = match args[i].as_str() {
+                        "fast" => true,
+                        "standard" => false,
+                        _ => return Err("SCD speed must be `fast` or `standard`".into()),
+                    };
+                }
+            }
+            "--scd-overlap" => {
+                scd_overlap = true;
+            }
+            "--no-scd" => {
+                no_scd = true;
+            }
+            "--chunk-frames" => {
+                i += 1;
+                if i < args.len() {
+                    let n: usize = args[i].parse()?;
+                    if n == 0 {
+                        return Err("Chunk frames must be greater than 0".into());
+                    }
+                    chunk_frames = Some(n);
+                    no_scd = true;
+                }
+            }
+            "--min-scene" => {
+                i += 1;
+                if i < args.len() {
+                    scene_len.0 = Some(chunk::SceneLen::parse(&args[i])?);
+                }
+            }
+            "--max-scene" => {
+                i += 1;
+                if i < args.len() {
+                    scene_len.1 = Some(chunk::SceneLen::parse(&args[i])?);
+                }
+            }
             #[cfg(feature = "vship")]
             "-t" | "--tq" => {
                 i += 1;
@@ -193,18 +623,308 @@ fn get_args(args: &[String]) -> Result<Args, Box<dyn std::error::Error>> {
                     qp_range = Some(args[i].clone());
                 }
             }
+            #[cfg(feature = "vship")]
+            "--max-rounds" => {
+                i += 1;
+                if i < args.len() {
+                    max_rounds = args[i].parse()?;
+                    if max_rounds < 2 {
+                        return Err("Max rounds must be at least 2".into());
+                    }
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--xpsnr" => {
+                xpsnr = true;
+            }
+            #[cfg(feature = "vship")]
+            "--crf-step" => {
+                i += 1;
+                if i < args.len() {
+                    crf_step = args[i].parse()?;
+                    if ![0.25, 0.1, 0.05].contains(&crf_step) {
+                        return Err("CRF step must be 0.25, 0.1 or 0.05".into());
+                    }
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--metric-res" => {
+                i += 1;
+                if i < args.len() {
+                    metric_res = Some(args[i].parse()?);
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--metric-stride" => {
+                i += 1;
+                if i < args.len() {
+                    metric_stride = args[i].parse()?;
+                    if metric_stride == 0 {
+                        return Err("Metric stride must be at least 1".into());
+                    }
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--metric-workers" => {
+                i += 1;
+                if i < args.len() {
+                    metric_workers = args[i].parse()?;
+                    if metric_workers == 0 {
+                        return Err("Metric workers must be at least 1".into());
+                    }
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--guard" => {
+                i += 1;
+                if i < args.len() {
+                    guard = Some(tq::Guard::parse(&args[i])?);
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--probe-preset" => {
+                i += 1;
+                if i < args.len() {
+                    probe_preset = Some(args[i].parse()?);
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--probe-log" => {
+                i += 1;
+                if i < args.len() {
+                    probe_log = Some(PathBuf::from(&args[i]));
+                }
+            }
+            #[cfg(feature = "vship")]
+            "--gpu" => {
+                i += 1;
+                if i < args.len() {
+                    let ids: Vec<i32> =
+                        args[i].split(',').map(str::parse).collect::<Result<_, _>>()?;
+                    if ids.is_empty() {
+                        return Err("GPU list must not be empty".into());
+                    }
+                    gpu = Some(ids);
+                }
+            }
             "-p" | "--param" => {
                 i += 1;
                 if i < args.len() {
                     params.clone_from(&args[i]);
                 }
             }
+            "--preset" => {
+                i += 1;
+                if i < args.len() {
+                    let val: i8 = args[i].parse()?;
+                    if !(-1..=13).contains(&val) {
+                        return Err("Preset must be between -1 and 13".into());
+                    }
+                    preset = Some(val);
+                }
+            }
+            "--lp" => {
+                i += 1;
+                if i < args.len() {
+                    let val: u32 = args[i].parse()?;
+                    if val == 0 {
+                        return Err("Logical processors must be at least 1".into());
+                    }
+                    lp = Some(val);
+                }
+            }
+            "--tune" => {
+                i += 1;
+                if i < args.len() {
+                    let val: u8 = args[i].parse()?;
+                    if val > 4 {
+                        return Err("Tune must be between 0 and 4".into());
+                    }
+                    tune = Some(val);
+                }
+            }
+            "--rc" => {
+                i += 1;
+                if i < args.len() {
+                    rc_qp = match args[i].as_str() {
+                        "qp" => true,
+                        "crf" => false,
+                        _ => return Err("Rate control must be `crf` or `qp`".into()),
+                    };
+                }
+            }
+            "--profile" => {
+                i += 1;
+                if i < args.len() {
+                    profile = Some(svt::profile(&args[i]).ok_or_else(|| {
+                        format!("Unknown profile `{}`, use film, anime, grain or screen", args[i])
+                    })?);
+                }
+            }
+            "--pool" => {
+                i += 1;
+                if i < args.len() {
+                    pool = args[i].parse()?;
+                }
+            }
+            "--mem" => {
+                i += 1;
+                if i < args.len() {
+                    let gb: f64 = args[i].parse()?;
+                    if gb <= 0.0 {
+                        return Err("Memory budget must be above 0 GB".into());
+                    }
+                    mem = Some((gb * 1024.0 * 1024.0 * 1024.0) as usize);
+                }
+            }
+            "--target-size" => {
+                i += 1;
+                if i < args.len() {
+                    let mb: f64 = args[i].parse()?;
+                    if mb <= 0.0 {
+                        return Err("Target size must be above 0 MB".into());
+                    }
+                    target_size = Some(mb);
+                }
+            }
+            "--two-pass" => {
+                two_pass = true;
+            }
+            "--sequential" => {
+                sequential = true;
+            }
+            "--stream" => {
+                stream = true;
+            }
+            "--pin" => {
+                pin = true;
+            }
+            "--output-depth" => {
+                i += 1;
+                if i < args.len() {
+                    output_depth = match args[i].as_str() {
+                        "8" => 8,
+                        "10" => 10,
+                        _ => return Err("Output depth must be 8 or 10".into()),
+                    };
+                }
+            }
+            "--split" => {
+                i += 1;
+                if i < args.len() {
+                    let val: usize = args[i].parse()?;
+                    if val == 0 {
+                        return Err("Split length must be at least 1 frame".into());
+                    }
+                    split = Some(val);
+                }
+            }
+            "--trim" => {
+                i += 1;
+                if i < args.len() {
+                    let (start, end) = args[i]
+                        .split_once(':')
+                        .ok_or("Trim must be `start:end`, e.g. `1000:2000`")?;
+                    let (start, end): (usize, usize) = (start.parse()?, end.parse()?);
+                    if end <= start {
+                        return Err("Trim end must be after its start".into());
+                    }
+                    trim = Some((start, end));
+                }
+            }
+            "--fps" => {
+                i += 1;
+                if i < args.len() {
+                    fps = Some(parse_fps(&args[i])?);
+                }
+            }
+            "--sample" => {
+                i += 1;
+                if i < args.len() {
+                    let val: usize = args[i].parse()?;
+                    if val == 0 {
+                        return Err("Sample must be at least 1 chunk".into());
+                    }
+                    sample = Some(val);
+                }
+            }
+            "--merge" => {
+                merge = true;
+            }
             "-r" | "--resume" => {
                 resume = true;
             }
             "-q" | "--quiet" => {
                 quiet = true;
             }
+            "--json" => {
+                json = true;
+                quiet = true;
+            }
+            "--name" => {
+                i += 1;
+                if i < args.len() {
+                    name = Some(args[i].clone());
+                }
+            }
+            "--dry-run" => {
+                dry_run = true;
+            }
+            "--embed-settings" => {
+                embed_settings = true;
+            }
+            "--chapters" => {
+                chapters = true;
+            }
+            "--keep-workdir" => {
+                keep_workdir = true;
+            }
+            "--overwrite" => {
+                overwrite = true;
+            }
+            "--keep-meta" => {
+                keep_meta = true;
+            }
+            "--hdr10plus" => {
+                if !dyn_meta.contains(&dynmeta::DynMeta::Hdr10Plus) {
+                    dyn_meta.push(dynmeta::DynMeta::Hdr10Plus);
+                }
+            }
+            "--dovi" => {
+                if !dyn_meta.contains(&dynmeta::DynMeta::DolbyVision) {
+                    dyn_meta.push(dynmeta::DynMeta::DolbyVision);
+                }
+            }
+            "--verify" => {
+                verify = true;
+            }
+            "--print-command" => {
+                print_command = true;
+            }
+            "--subs" => {
+                i += 1;
+                if i < args.len() {
+                    subs = Some(args[i].split(',').map(str::to_lowercase).collect());
+                }
+            }
+            "--log" => {
+                i += 1;
+                if i < args.len() {
+                    log = Some(PathBuf::from(&args[i]));
+                }
+            }
+            "--progress-socket" => {
+                i += 1;
+                if i < args.len() {
+                    progress_socket = Some(PathBuf::from(&args[i]));
+                }
+            }
+            "--work-dir" => {
+                i += 1;
+                if i < args.len() {
+                    work_root = Some(PathBuf::from(&args[i]));
+                }
+            }
             "-n" | "--noise" => {
                 i += 1;
                 if i < args.len() {
@@ -215,6 +935,38 @@ fn get_args(args: &[String]) -> Result<Args, Box<dyn std::error::Error>> {
                     noise = Some(val * 100);
                 }
             }
+            "--seed" => {
+                i += 1;
+                if i < args.len() {
+                    seed = Some(args[i].parse()?);
+                }
+            }
+            "--denoise" => {
+                i += 1;
+                if i < args.len() {
+                    let val: u8 = args[i].parse()?;
+                    if !(1..=50).contains(&val) {
+                        return Err("Denoise strength must be between 1-50".into());
+                    }
+                    denoise = Some(val);
+                }
+            }
+            "--film-grain" => {
+                i += 1;
+                if i < args.len() {
+                    let val: u8 = args[i].parse()?;
+                    if !(1..=50).contains(&val) {
+                        return Err("Film grain level must be between 1-50".into());
+                    }
+                    film_grain = Some(val);
+                }
+            }
+            "--grain-table" => {
+                i += 1;
+                if i < args.len() {
+                    grain_table = Some(PathBuf::from(&args[i]));
+                }
+            }
             "-c" | "--crop" => {
                 i += 1;
                 if i < args.len() {
@@ -227,46 +979,279 @@ fn get_args(args: &[String]) -> Result<Args, Box<dyn std::error::Error>> {
                     audio = Some(audio::parse_audio_arg(&args[i])?);
                 }
             }
-
-            arg if !arg.starts_with('-') => {
-                if input == PathBuf::new() {
-                    input = PathBuf::from(arg);
-                } else if output == PathBuf::new() {
-                    output = PathBuf::from(arg);
-                }
+            "--audio-delay" => {
+                i += 1;
+                if i < args.len() {
+                    audio_delay = Some(audio::AudioDelay::parse(&args[i])?);
+                }
+            }
+            "--audio-only" => {
+                i += 1;
+                if i < args.len() {
+                    audio_only = Some(PathBuf::from(&args[i]));
+                }
+            }
+
+            "-" if input == PathBuf::new() => {
+                stdin = true;
+            }
+            arg if !arg.starts_with('-') => {
+                if input == PathBuf::new() {
+                    input = PathBuf::from(arg);
+                } else if output == PathBuf::new() {
+                    output = PathBuf::from(arg);
+                }
             }
             _ => return Err(format!("Unknown argument: {}", args[i]).into()),
         }
         i += 1;
     }
 
-    if resume && let Ok(mut saved_args) = get_saved_args(&input) {
-        saved_args.resume = true;
-        return Ok(saved_args);
+    if let Some(delay) = audio_delay {
+        let Some(ref mut spec) = audio else {
+            return Err("`--audio-delay` needs `-a`".into());
+        };
+        spec.delay = delay;
+    }
+    if audio_only.is_some() && (audio.is_none() || resume || stdin) {
+        return Err("`--audio-only` needs `-a` and cannot be used with `-r` or stdin input".into());
+    }
+
+    if output.extension().is_some_and(|e| e.eq_ignore_ascii_case("avif")) {
+        if audio.is_some()
+            || chapters
+            || subs.is_some()
+            || split.is_some()
+            || chunk_frames.is_some()
+            || scene_file != PathBuf::new()
+        {
+            return Err("`.avif` output is a single chunk without audio, so `-a`, `--chapters`, \
+                        `--subs`, `-s`, `--split` and `--chunk-frames` do not apply"
+                .into());
+        }
+        no_scd = true;
     }
 
+    #[cfg(feature = "vship")]
+    if metric_workers > 0 && target_quality.is_none() {
+        return Err("`--metric-workers` needs `-t`".into());
+    }
+
+    if no_scd && scene_file != PathBuf::new() {
+        return Err("`--no-scd` and `--chunk-frames` cannot be combined with `-s`".into());
+    }
+    if chunk_frames.is_some() && split.is_some() {
+        return Err("`--chunk-frames` and `--split` cannot be combined".into());
+    }
+    if no_scd && chunk_frames.is_none() {
+        stream = true;
+    }
+
+    if stdin {
+        if output == PathBuf::new() {
+            return Err("Reading from stdin needs an <OUTPUT> path".into());
+        }
+        if resume || audio.is_some() || chapters {
+            return Err("`-r`, `-a` and `--chapters` cannot be used when reading from stdin".into());
+        }
+        input = work_dir_of(&output, work_root.as_deref(), false).join("stdin.y4m");
+    }
+
+    if rc_qp {
+        if svt::get_param(&params, "--crf").is_some() {
+            return Err("`--rc qp` takes a `--qp` in `-p`, not `--crf`".into());
+        }
+        params = svt::set_param(&params, "--aq-mode", "0");
+        #[cfg(feature = "vship")]
+        {
+            crf_step = 1.0;
+        }
+    }
+    for &(key, value) in profile.unwrap_or_default() {
+        if svt::get_param(&params, key).is_none() {
+            params = svt::set_param(&params, key, value);
+        }
+    }
+    if let Some(p) = preset {
+        params = svt::set_param(&params, "--preset", &p.to_string());
+    }
+    if let Some(p) = lp {
+        params = svt::set_param(&params, "--lp", &p.to_string());
+    }
+    if let Some(p) = tune {
+        params = svt::set_param(&params, "--tune", &p.to_string());
+    }
+    if let Some(g) = film_grain {
+        if noise.is_some() || grain_table.is_some() || denoise.is_some() {
+            return Err("`--film-grain` cannot be combined with `-n`, `--grain-table` or \
+                        `--denoise`"
+                .into());
+        }
+        params = svt::set_param(&params, "--film-grain", &g.to_string());
+    }
+    if let Some(d) = denoise {
+        params = svt::set_param(&params, "--film-grain", &d.to_string());
+        params = svt::set_param(&params, "--film-grain-denoise", "1");
+    }
+
+    let params_given = !params.is_empty();
+    #[cfg(feature = "vship")]
+    let qp_given = qp_range.is_some();
+
     let mut result = Args {
         worker,
+        threads,
         scene_file,
+        scd_fast,
+        scd_overlap,
+        no_scd,
+        chunk_frames,
+        scene_len,
         #[cfg(feature = "vship")]
         target_quality,
         #[cfg(feature = "vship")]
         metric_mode,
         #[cfg(feature = "vship")]
         qp_range,
+        #[cfg(feature = "vship")]
+        max_rounds,
+        #[cfg(feature = "vship")]
+        probe_log,
+        #[cfg(feature = "vship")]
+        gpu,
+        #[cfg(feature = "vship")]
+        xpsnr,
+        #[cfg(feature = "vship")]
+        probe_preset,
+        #[cfg(feature = "vship")]
+        crf_step,
+        #[cfg(feature = "vship")]
+        metric_res,
+        #[cfg(feature = "vship")]
+        metric_stride,
+        #[cfg(feature = "vship")]
+        metric_workers,
+        #[cfg(feature = "vship")]
+        guard,
         params,
+        rc_qp,
+        target_size,
+        two_pass,
+        sequential,
+        stream,
+        output_depth,
+        pin,
+        split,
+        trim,
+        sample,
+        fps,
+        merge,
         resume,
         quiet,
+        json,
+        embed_settings,
+        dry_run,
+        chapters,
+        keep_workdir,
+        overwrite,
+        keep_meta,
+        dyn_meta,
+        verify,
+        print_command,
+        log,
+        progress_socket,
+        work_root,
+        subs,
+        pool,
+        mem,
         noise,
+        seed,
+        grain_table,
         crop,
         crop_str,
         audio,
+        audio_only,
         input,
+        stdin,
         output,
+        name,
     };
 
     apply_defaults(&mut result);
 
+    if resume && let Ok(mut saved_args) = get_saved_args(&result.input, result.work_root.as_deref())
+    {
+        let mut diffs = Vec::new();
+        let mut check = |flag: &str, given: bool, new: String, old: String| {
+            if given && new != old {
+                diffs.push(format!("{flag} `{new}` (saved `{old}`)"));
+            }
+        };
+        check("-p", params_given, result.params.clone(), saved_args.params.clone());
+        #[cfg(feature = "vship")]
+        {
+            check(
+                "-t",
+                result.target_quality.is_some(),
+                result.target_quality.clone().unwrap_or_default(),
+                saved_args.target_quality.clone().unwrap_or_default(),
+            );
+            check(
+                "-f",
+                qp_given,
+                result.qp_range.clone().unwrap_or_default(),
+                saved_args.qp_range.clone().unwrap_or_default(),
+            );
+        }
+        check(
+            "-c",
+            result.crop_str.is_some(),
+            result.crop_str.clone().unwrap_or_default(),
+            saved_args.crop_str.clone().unwrap_or_default(),
+        );
+        check(
+            "--trim",
+            result.trim.is_some(),
+            result.trim.map(|(s, e)| format!("{s}:{e}")).unwrap_or_default(),
+            saved_args.trim.map(|(s, e)| format!("{s}:{e}")).unwrap_or_default(),
+        );
+        check(
+            "--fps",
+            result.fps.is_some(),
+            result.fps.map(|(n, d)| format!("{n}/{d}")).unwrap_or_default(),
+            saved_args.fps.map(|(n, d)| format!("{n}/{d}")).unwrap_or_default(),
+        );
+        check(
+            "-n",
+            result.noise.is_some(),
+            result.noise.map(|n| n.to_string()).unwrap_or_default(),
+            saved_args.noise.map(|n| n.to_string()).unwrap_or_default(),
+        );
+        check(
+            "--seed",
+            result.seed.is_some(),
+            result.seed.map(|s| s.to_string()).unwrap_or_default(),
+            saved_args.seed.map(|s| s.to_string()).unwrap_or_default(),
+        );
+        check(
+            "--grain-table",
+            result.grain_table.is_some(),
+            result.grain_table.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+            saved_args.grain_table.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+        );
+
+        if !diffs.is_empty() {
+            return Err(format!(
+                "Resume settings differ from the saved encode: {}. Run without `-r` to start over",
+                diffs.join(", ")
+            )
+            .into());
+        }
+
+        saved_args.resume = true;
+        return Ok(saved_args);
+    }
+
     if result.worker == 0
         || result.scene_file == PathBuf::new()
         || result.input == PathBuf::new()
@@ -275,6 +1260,76 @@ fn get_args(args: &[String]) -> Result<Args, Box<dyn std::error::Error>> {
         return Err("Missing required arguments".into());
     }
 
+    if result.trim.is_some() && (result.audio.is_some() || result.chapters) {
+        return Err("`--trim` cannot be combined with `-a` or `--chapters`".into());
+    }
+
+    if result.sample.is_some() && (result.audio.is_some() || result.chapters) {
+        return Err("`--sample` cannot be combined with `-a` or `--chapters`".into());
+    }
+
+    #[cfg(feature = "vship")]
+    if result.output_depth == 8 && result.target_quality.is_some() {
+        return Err("`--output-depth 8` cannot be combined with `-t`".into());
+    }
+
+    #[cfg(feature = "vship")]
+    if result.two_pass && result.target_quality.is_some() {
+        return Err("`--two-pass` cannot be combined with `-t`".into());
+    }
+
+    #[cfg(feature = "vship")]
+    if result.target_size.is_some() && result.target_quality.is_some() {
+        return Err("`--target-size` cannot be combined with `-t`".into());
+    }
+
+    #[cfg(feature = "vship")]
+    if let Some(g) = result.guard {
+        let Some(ref tq) = result.target_quality else {
+            return Err("`--guard` needs `-t`".into());
+        };
+        if result.xpsnr {
+            return Err("`--guard` cannot be combined with `--xpsnr`".into());
+        }
+        let parts: Vec<f64> = tq.split('-').filter_map(|s| s.parse().ok()).collect();
+        if let [lo, hi] = parts[..]
+            && match g {
+                tq::Guard::Ssimu2(_) => f64::midpoint(lo, hi) > 10.0,
+                tq::Guard::Butteraugli(_) => f64::midpoint(lo, hi) < 8.0,
+            }
+        {
+            return Err("`--guard` must use another metric than `-t`".into());
+        }
+    }
+
+    #[cfg(feature = "vship")]
+    if result.metric_stride > 1 {
+        let Some(ref tq) = result.target_quality else {
+            return Err("`--metric-stride` needs `-t`".into());
+        };
+        let parts: Vec<f64> = tq.split('-').filter_map(|s| s.parse().ok()).collect();
+        let cvvdp = parts.len() == 2 && {
+            let target = f64::midpoint(parts[0], parts[1]);
+            target > 8.0 && target <= 10.0
+        };
+        if cvvdp || result.xpsnr {
+            return Err("`--metric-stride` cannot be used with CVVDP or XPSNR, which need every \
+                        frame in order"
+                .into());
+        }
+    }
+
+    if result.seed.is_some() && result.noise.is_none() {
+        return Err("`--seed` needs `-n`".into());
+    }
+
+    if let Some(ref path) = result.grain_table {
+        if result.noise.is_some() {
+            return Err("`-n` and `--grain-table` cannot be combined".into());
+        }
+        noise::check_table(path)?;
+    }
+
     Ok(result)
 }
 
@@ -284,6 +1339,32 @@ fn hash_input(path: &Path) -> String {
     format!("{:x}", hasher.finish())
 }
 
+fn save_meta(
+    args: &Args,
+    work_dir: &Path,
+    inf: &ffms::VidInf,
+    grain_table: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stem = args.output.file_stem().unwrap().to_string_lossy();
+    let dir = args.output.with_file_name(".xav-meta").join(&*stem);
+    fs::create_dir_all(&dir)?;
+
+    if !args.no_scd {
+        fs::copy(&args.scene_file, dir.join("scd.txt"))?;
+    }
+    fs::copy(work_dir.join("cmd.txt"), dir.join("cmd.txt"))?;
+    if let Some(grain) = grain_table {
+        fs::copy(grain, dir.join("grain.tbl"))?;
+    }
+    let crop = args.crop.unwrap_or_default();
+    fs::write(dir.join("svt.txt"), svt::enc_cmd_line(inf, &args.params, crop, grain_table))?;
+
+    if !args.quiet {
+        eprintln!("{Y}Meta kept: {W}{}{N}", dir.display());
+    }
+    Ok(())
+}
+
 fn save_args(work_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
     let cmd: Vec<String> =
         std::env::args().filter(|arg| arg != "-r" && arg != "--resume").collect();
@@ -292,12 +1373,43 @@ fn save_args(work_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
         .map(|arg| if arg.contains(' ') { format!("\"{arg}\"") } else { arg.clone() })
         .collect();
     fs::write(work_dir.join("cmd.txt"), quoted_cmd.join(" "))?;
+    fs::write(work_dir.join("cwd.txt"), std::env::current_dir()?.as_os_str().as_encoded_bytes())?;
     Ok(())
 }
 
-fn get_saved_args(input: &Path) -> Result<Args, Box<dyn std::error::Error>> {
-    let hash = hash_input(input);
-    let work_dir = input.with_file_name(format!(".{}", &hash[..7]));
+fn input_fingerprint(input: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let meta = fs::metadata(input)?;
+    let mtime = meta.modified()?.duration_since(std::time::UNIX_EPOCH)?;
+    Ok(format!("{} {}.{:09}", meta.len(), mtime.as_secs(), mtime.subsec_nanos()))
+}
+
+fn work_dir_of(input: &Path, root: Option<&Path>, resume: bool) -> PathBuf {
+    let beside = input.with_file_name(format!(".{}", &hash_input(input)[..7]));
+    let root = root.map(Path::to_path_buf).or_else(|| {
+        std::env::var_os("TMPDIR").filter(|_| !resume || !beside.exists()).map(PathBuf::from)
+    });
+    match root {
+        Some(root) => {
+            let input = std::path::absolute(input).unwrap_or_else(|_| input.to_path_buf());
+            root.join(format!(".{}", &hash_input(&input)[..7]))
+        }
+        None => beside,
+    }
+}
+
+fn work_dir(args: &Args) -> PathBuf {
+    if args.stdin {
+        args.input.parent().unwrap().to_path_buf()
+    } else {
+        work_dir_of(&args.input, args.work_root.as_deref(), args.resume)
+    }
+}
+
+fn get_saved_args(
+    input: &Path,
+    work_root: Option<&Path>,
+) -> Result<Args, Box<dyn std::error::Error>> {
+    let work_dir = work_dir_of(input, work_root, true);
     let cmd_path = work_dir.join("cmd.txt");
 
     if cmd_path.exists() {
@@ -334,104 +1446,704 @@ fn parse_quoted_args(cmd_line: &str) -> Vec<String> {
     args
 }
 
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn write_tags(args: &Args, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    use std::fmt::Write;
+
+    let xml_str = |s: &str| {
+        s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+    };
+
+    let mut tags = vec![
+        ("XAV_VERSION", env!("CARGO_PKG_VERSION").to_string()),
+        ("ENCODER", "SvtAv1EncApp".to_string()),
+        ("ENCODER_SETTINGS", args.params.clone()),
+    ];
+    #[cfg(feature = "vship")]
+    if let Some(ref tq) = args.target_quality {
+        tags.push(("TARGET_QUALITY", tq.clone()));
+        tags.push(("METRIC_MODE", args.metric_mode.clone()));
+        if args.xpsnr {
+            tags.push(("METRIC", "XPSNR".to_string()));
+        }
+        if let Some(ref qp) = args.qp_range {
+            tags.push(("CRF_RANGE", qp.clone()));
+        }
+    }
+    if let Some(c) = args.crop {
+        tags.push(("CROP", format!("{},{},{},{}", c.top, c.bottom, c.left, c.right)));
+    }
+    if let Some(iso) = args.noise {
+        tags.push(("PHOTON_NOISE_ISO", iso.to_string()));
+    }
+    if let Some(seed) = args.seed {
+        tags.push(("PHOTON_NOISE_SEED", seed.to_string()));
+    }
+    if let Some(ref path) = args.grain_table {
+        tags.push(("GRAIN_TABLE", path.display().to_string()));
+    }
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE Tags SYSTEM \
+         \"matroskatags.dtd\">\n<Tags>\n  <Tag>\n    <Targets />\n",
+    );
+    for (name, value) in tags {
+        let _ = write!(
+            xml,
+            "    <Simple>\n      <Name>{name}</Name>\n      <String>{}</String>\n    </Simple>\n",
+            xml_str(&value)
+        );
+    }
+    xml.push_str("  </Tag>\n</Tags>\n");
+
+    fs::write(path, xml)?;
+    Ok(())
+}
+
+fn parse_fps(s: &str) -> Result<(u32, u32), Box<dyn std::error::Error>> {
+    let fps = if let Some((num, den)) = s.split_once('/') {
+        (num.parse()?, den.parse()?)
+    } else {
+        let v: f64 = s.parse()?;
+        let ntsc = (v * 1.001).round();
+        if v.fract() == 0.0 {
+            (v as u32, 1)
+        } else if (ntsc * 1000.0 / 1001.0 - v).abs() < 0.001 {
+            (ntsc as u32 * 1000, 1001)
+        } else {
+            ((v * 1000.0).round() as u32, 1000)
+        }
+    };
+    if fps.0 == 0 || fps.1 == 0 {
+        return Err(
+            format!("Invalid frame rate `{s}`, use `num/den` or a decimal like `23.976`").into()
+        );
+    }
+    Ok(fps)
+}
+
+fn check_output(args: &Args) -> Result<(), XavError> {
+    if args.output.exists() && !args.overwrite && !args.resume {
+        return Err(XavError::Args(format!(
+            "{} already exists, pass `--overwrite` to replace it",
+            args.output.display()
+        )));
+    }
+    Ok(())
+}
+
+fn remux_audio(
+    args: &Args,
+    container: chunk::Container,
+    video: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if !video.exists() {
+        return Err(XavError::Args(format!("{} does not exist", video.display())).into());
+    }
+    let in_place = args.output == video;
+    if !in_place {
+        check_output(args)?;
+    }
+
+    let output = if in_place {
+        let name = video.file_name().unwrap_or_default().to_string_lossy();
+        video.with_file_name(format!(".part.{name}"))
+    } else {
+        args.output.clone()
+    };
+    let mux = chunk::MuxOpts {
+        container,
+        tags: None,
+        chapters: args.chapters,
+        subs: args.subs.as_deref(),
+    };
+    let work_dir = work_dir_of(&args.input, args.work_root.as_deref(), false);
+    let created = !work_dir.exists();
+    fs::create_dir_all(&work_dir)?;
+    let res = audio::process_audio(
+        args.audio.as_ref().unwrap(),
+        &args.input,
+        video,
+        &work_dir,
+        &output,
+        mux,
+    );
+    if created {
+        let _ = fs::remove_dir_all(&work_dir);
+    }
+    if in_place && res.is_err() {
+        let _ = fs::remove_file(&output);
+    }
+    res.map_err(|e| error::classify(e, XavError::Mux))?;
+    if in_place {
+        fs::rename(&output, video)?;
+    }
+    Ok(args.output.clone())
+}
+
+fn check_space(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let work_dir = work_dir(args);
+    let Some(free) = tools::free_space(&work_dir) else { return Ok(()) };
+    let input = if args.stdin { 0 } else { fs::metadata(&args.input)?.len() };
+
+    #[cfg(feature = "vship")]
+    let probes = if args.target_quality.is_some() && args.keep_workdir {
+        input / 2 * args.max_rounds as u64
+    } else {
+        0
+    };
+    #[cfg(not(feature = "vship"))]
+    let probes = 0;
+    let mut need = input + probes;
+    if args.resume {
+        need = need.saturating_sub(tools::dir_size(&work_dir));
+    }
+
+    if free < need {
+        return Err(XavError::Space(format!(
+            "Not enough free space for the work dir in {}: about {} GB needed, {} GB free. Free \
+             some space or point `--work-dir` elsewhere",
+            work_dir.parent().unwrap_or(&work_dir).display(),
+            need.div_ceil(1_000_000_000),
+            free / 1_000_000_000
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn spool_stdin(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stdin = std::io::stdin().lock();
+    let mut magic = [0u8; 9];
+    stdin.read_exact(&mut magic)?;
+    if &magic != b"YUV4MPEG2" {
+        return Err(XavError::Args("stdin is not a y4m stream".to_string()).into());
+    }
+
+    let work_dir = work_dir(args);
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir)?;
+    }
+    let _ = SPOOL.set((work_dir.clone(), args.keep_workdir && !args.dry_run));
+    fs::create_dir_all(&work_dir)?;
+    let mut out = std::io::BufWriter::new(fs::File::create(&args.input)?);
+    out.write_all(&magic)?;
+    std::io::copy(&mut stdin, &mut out).and_then(|_| out.flush()).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::StorageFull {
+            XavError::Space(format!("Disk full while buffering stdin in {}", work_dir.display()))
+                .into()
+        } else {
+            Box::<dyn std::error::Error>::from(e)
+        }
+    })
+}
+
 fn ensure_scene_file(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
-    if !args.scene_file.exists() {
-        scd::fd_scenes(&args.input, &args.scene_file, args.quiet)?;
+    if !args.no_scd && !args.scene_file.exists() {
+        scd::fd_scenes(
+            &args.input,
+            &args.scene_file,
+            args.quiet,
+            args.scd_fast,
+            args.scene_len,
+            args.fps,
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn plan_chunks(
+    args: &Args,
+    inf: &mut ffms::VidInf,
+    min_len: usize,
+    max_len: usize,
+) -> Result<Vec<chunk::Chunk>, XavError> {
+    let fps = f64::from(inf.fps_num) / f64::from(inf.fps_den);
+    let mut scenes = if let Some(n) = args.chunk_frames {
+        (0..inf.frames)
+            .step_by(n)
+            .map(|s| chunk::Scene { s_frame: s, e_frame: (s + n).min(inf.frames) })
+            .collect()
+    } else if args.no_scd {
+        #[cfg(feature = "vship")]
+        if args.target_quality.is_some() && inf.frames > max_len {
+            return Err(XavError::Args(format!(
+                "`--no-scd` with `-t` buffers the whole file, {} frames is over the {max_len} \
+                 frame scene limit. Use `--chunk-frames` instead",
+                inf.frames
+            )));
+        }
+        vec![chunk::Scene { s_frame: 0, e_frame: inf.frames }]
+    } else {
+        chunk::load_scenes(&args.scene_file, inf.frames, fps)
+            .map_err(|e| XavError::Scenes(e.to_string()))?
+    };
+    if let Some((start, end)) = args.trim {
+        if start >= inf.frames {
+            return Err(XavError::Args(format!(
+                "Trim start {start} is past the last frame ({})",
+                inf.frames - 1
+            )));
+        }
+        let end = end.min(inf.frames);
+        scenes = chunk::trim_scenes(&scenes, (start, end), min_len, max_len);
+        inf.frames = end - start;
+    }
+    if args.merge {
+        scenes = chunk::merge_short(&scenes, min_len, max_len);
+    }
+    if !args.no_scd {
+        chunk::validate_scenes(
+            &scenes,
+            min_len,
+            if args.split.is_some() { usize::MAX } else { max_len },
+        )
+        .map_err(|e| XavError::Scenes(e.to_string()))?;
+    }
+
+    let mut chunks = chunk::chunkify(&scenes, args.split);
+    if let Some(n) = args.sample
+        && n < chunks.len()
+    {
+        let len = chunks.len();
+        chunks = (0..n).map(|i| chunks[(2 * i + 1) * len / (2 * n)].clone()).collect();
+        inf.frames = chunks.iter().map(|c| c.end - c.start).sum();
+    }
+    Ok(chunks)
+}
+
+fn resolve_crop(
+    args: &mut Args,
+    idx: &std::sync::Arc<ffms::VidIdx>,
+    inf: &ffms::VidInf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(ref s) = args.crop_str {
+        args.crop = Some(if s == "auto" {
+            let c = crop::detect(idx, inf, args.threads)?;
+            let (w, h) = c.dims(inf.width, inf.height);
+            eprintln!(
+                "{Y}Auto crop: {W}{},{},{},{} {C}({w}x{h}){N}",
+                c.top, c.bottom, c.left, c.right
+            );
+            c
+        } else {
+            crop::parse_crop(s, inf).map_err(|e| XavError::Args(e.to_string()))?
+        });
+    }
+    Ok(())
+}
+
+fn size_crf(
+    args: &Args,
+    chunks: &[chunk::Chunk],
+    inf: &ffms::VidInf,
+    idx: &std::sync::Arc<ffms::VidIdx>,
+    work_dir: &Path,
+    grain_table: Option<&Path>,
+) -> Result<(f32, u64), Box<dyn std::error::Error>> {
+    let path = work_dir.join("size.txt");
+    if args.resume
+        && let Some(saved) = fs::read_to_string(&path).ok().and_then(|s| {
+            let (crf, pred) = s.trim().split_once(' ')?;
+            Some((crf.parse().ok()?, pred.parse().ok()?))
+        })
+    {
+        return Ok(saved);
+    }
+
+    let (crf, pred) = svt::size_search(chunks, inf, args, idx, work_dir, grain_table)?;
+    fs::write(&path, format!("{crf} {pred}"))?;
+    if !args.quiet {
+        eprintln!("{Y}Target size: {W}CRF {crf:.2} {C}({:.1} MB predicted){N}", pred as f64 / 1e6);
+    }
+    Ok((crf, pred))
+}
+
+fn source_err(args: &Args, e: Box<dyn std::error::Error>) -> XavError {
+    XavError::Source(format!("{}: {e}", args.input.display()))
+}
+
+fn vid_info(
+    args: &Args,
+    idx: &std::sync::Arc<ffms::VidIdx>,
+) -> Result<ffms::VidInf, Box<dyn std::error::Error>> {
+    let mut inf = ffms::get_vidinf(idx)?;
+    if inf.width % 2 != 0 || inf.height % 2 != 0 {
+        let (width, height) = (inf.width & !1, inf.height & !1);
+        eprintln!(
+            "{R}Warning: {W}{}x{} source has odd dimensions, the last odd column/row is dropped \
+             and {width}x{height} is encoded{N}",
+            inf.width, inf.height
+        );
+        inf.width = width;
+        inf.height = height;
+    }
+    if let Some((num, den)) = args.fps {
+        inf.fps_num = num;
+        inf.fps_den = den;
+    } else {
+        warn_vfr(idx, &inf)?;
+    }
+    Ok(inf)
+}
+
+fn warn_vfr(
+    idx: &std::sync::Arc<ffms::VidIdx>,
+    inf: &ffms::VidInf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(avg) = ffms::vfr_avg_fps(idx)? {
+        eprintln!(
+            "{R}Warning: {W}variable frame rate source (average {avg:.3} fps). The output is \
+             muxed at a constant {:.3} fps and can drift out of sync with audio{N}",
+            f64::from(inf.fps_num) / f64::from(inf.fps_den)
+        );
+    }
+    Ok(())
+}
+
+fn verify_output(args: &Args, inf: &ffms::VidInf) -> Result<(), Box<dyn std::error::Error>> {
+    let idx = ffms::VidIdx::new(&args.output, args.quiet)?;
+    let out = ffms::get_vidinf(&idx)?;
+    let (w, h) = args.crop.map_or((inf.width, inf.height), |c| c.dims(inf.width, inf.height));
+
+    let mut errs = Vec::new();
+    if out.frames != inf.frames {
+        errs.push(format!("{} frames, expected {}", out.frames, inf.frames));
+    }
+    if (out.width, out.height) != (w, h) {
+        errs.push(format!("{}x{}, expected {w}x{h}", out.width, out.height));
+    }
+
+    let source = ffms::thr_vid_src(&idx, args.threads.try_into().unwrap_or(8))?;
+    let mut frame = vec![0u8; ffms::calc_10bit_size(&out)];
+    let mut progs = progs::ProgsBar::new(args.quiet);
+    let mut bad = 0;
+    for i in 0..out.frames {
+        if ffms::extr_10bit(source, i, &mut frame).is_err() {
+            bad += 1;
+        }
+        if i % 32 == 0 || i + 1 == out.frames {
+            progs.up_verify(i + 1, out.frames);
+        }
+    }
+    progs.finish();
+    ffms::destroy_vid_src(source);
+    drop(idx);
+    let _ = fs::remove_file(format!("{}.ffidx", args.output.display()));
+
+    if bad > 0 {
+        errs.push(format!("{bad} frames failed to decode"));
+    }
+    if !errs.is_empty() {
+        return Err(XavError::Encode(format!(
+            "Verifying {} failed: {}",
+            args.output.display(),
+            errs.join(", ")
+        ))
+        .into());
     }
+    eprintln!("{G}Verified: {W}{} frames, {w}x{h}{N}", out.frames);
     Ok(())
 }
 
-fn main_with_args(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+fn expand_output(args: &mut Args, inf: &ffms::VidInf) {
+    if args.name.is_none() {
+        return;
+    }
+    let (w, h) = args.crop.map_or((inf.width, inf.height), |c| c.dims(inf.width, inf.height));
+    args.output = PathBuf::from(
+        args.output
+            .to_string_lossy()
+            .replace("{width}", &w.to_string())
+            .replace("{height}", &h.to_string()),
+    );
+}
+
+fn dry_run(args: &Args) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    ensure_scene_file(args).map_err(|e| XavError::Scenes(e.to_string()))?;
+
+    let idx = ffms::VidIdx::new(&args.input, args.quiet).map_err(|e| source_err(args, e))?;
+    let mut inf = vid_info(args, &idx).map_err(|e| source_err(args, e))?;
+    let mut args = args.clone();
+    resolve_crop(&mut args, &idx, &inf)?;
+    expand_output(&mut args, &inf);
+
+    let (min_len, max_len) =
+        chunk::scene_limits(inf.fps_num, inf.fps_den, args.scene_len).map_err(XavError::Args)?;
+    let chunks = plan_chunks(&args, &mut inf, min_len, max_len)?;
+    let mut lens: Vec<usize> = chunks.iter().map(|c| c.end - c.start).collect();
+    lens.sort_unstable();
+
+    let crop = args.crop.unwrap_or_default();
+    let (width, height) = crop.dims(inf.width, inf.height);
+    println!("{Y}Output: {W}{}{N}", args.output.display());
+    println!(
+        "{Y}Video: {W}{width}x{height} {C}{}-bit, {:.3} fps, {} frames{N}",
+        inf.bit_depth,
+        f64::from(inf.fps_num) / f64::from(inf.fps_den),
+        inf.frames
+    );
+    println!(
+        "{Y}Chunks: {W}{} {C}(min {}, median {}, mean {:.1}, max {} frames){N}",
+        chunks.len(),
+        lens.first().unwrap_or(&0),
+        lens.get(lens.len() / 2).unwrap_or(&0),
+        lens.iter().sum::<usize>() as f64 / lens.len().max(1) as f64,
+        lens.last().unwrap_or(&0)
+    );
+    if let Some(iso) = args.noise {
+        let seed = args.seed.map(|s| format!(" {C}seed {s}")).unwrap_or_default();
+        println!("{Y}Noise: {W}ISO{iso}{seed}{N}");
+    }
+    if let Some(ref path) = args.grain_table {
+        println!("{Y}Grain: {W}{}{N}", path.display());
+    }
+    #[cfg(feature = "vship")]
+    if let (Some(tq), Some(qp)) = (&args.target_quality, &args.qp_range) {
+        println!("{Y}TQ: {W}{tq} {C}CRF {qp}, {}{N}", args.metric_mode);
+    }
+
+    let grain = args.grain_table.as_deref().or(args.noise.map(|_| Path::new("grain.tbl")));
+    println!("{Y}SVT: {W}{}{N}", svt::enc_cmd_line(&inf, &args.params, crop, grain));
+
+    if let Some(ref spec) = args.audio {
+        for line in audio::describe(spec, &args.input)? {
+            println!("{Y}Audio: {W}{line}{N}");
+        }
+    }
+
+    Ok(args.output)
+}
+
+fn main_with_args(args: &Args) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    PRINT_CMDS.store(args.print_command, Ordering::Relaxed);
+    let container =
+        chunk::Container::from_path(&args.output).map_err(|e| XavError::Args(e.to_string()))?;
+    tools::preflight(args, container)?;
+    if let Some(ref video) = args.audio_only {
+        return remux_audio(args, container, video);
+    }
+    if !args.dry_run {
+        check_output(args)?;
+        check_space(args)?;
+        if args.work_root.is_none()
+            && let Some(tmp) = std::env::var_os("TMPDIR")
+            && let dir = work_dir(args)
+            && dir.starts_with(tmp)
+        {
+            eprintln!("{Y}Work dir from $TMPDIR: {W}{}{N}", dir.display());
+        }
+    }
+
+    if args.stdin {
+        spool_stdin(args)?;
+    }
+
+    if args.dry_run {
+        return dry_run(args);
+    }
+
     if !args.quiet {
+        TUI.store(true, Ordering::Relaxed);
         print!("\x1b[?1049h\x1b[H\x1b[?25l");
         std::io::stdout().flush().unwrap();
     }
+    if let Some(ref path) = args.progress_socket {
+        progs::open_socket(path).map_err(|e| {
+            XavError::Args(format!("Cannot open progress socket {}: {e}", path.display()))
+        })?;
+    }
 
-    ensure_scene_file(args)?;
+    let overlap = args.scd_overlap
+        && !args.no_scd
+        && !args.scene_file.exists()
+        && args.trim.is_none()
+        && args.sample.is_none()
+        && args.target_size.is_none()
+        && args.dyn_meta.is_empty();
+    if !overlap {
+        ensure_scene_file(args).map_err(|e| XavError::Scenes(e.to_string()))?;
+    }
 
     if !args.quiet {
         println!();
     }
 
-    let hash = hash_input(&args.input);
-    let work_dir = args.input.with_file_name(format!(".{}", &hash[..7]));
+    let work_dir = work_dir(args);
 
     let is_new_encode = !work_dir.exists();
 
-    if !args.resume && work_dir.exists() {
+    if !args.resume && !args.stdin && work_dir.exists() {
         fs::remove_dir_all(&work_dir)?;
     }
 
     fs::create_dir_all(work_dir.join("split"))?;
     fs::create_dir_all(work_dir.join("encode"))?;
 
-    if is_new_encode {
+    let fingerprint = input_fingerprint(&args.input)?;
+    if is_new_encode || !args.resume {
         save_args(&work_dir)?;
+        fs::write(work_dir.join("src.txt"), &fingerprint)?;
+    } else if let Ok(saved) = fs::read_to_string(work_dir.join("src.txt"))
+        && saved != fingerprint
+    {
+        return Err(XavError::Args(format!(
+            "{} changed since the saved encode, run without `-r` to start over",
+            args.input.display()
+        ))
+        .into());
     }
 
-    let idx = ffms::VidIdx::new(&args.input, args.quiet)?;
-    let inf = ffms::get_vidinf(&idx)?;
+    let idx = ffms::VidIdx::new(&args.input, args.quiet).map_err(|e| source_err(args, e))?;
+    let mut inf = vid_info(args, &idx).map_err(|e| source_err(args, e))?;
+    if args.output_depth == 8 {
+        if inf.is_10bit() {
+            eprintln!(
+                "{Y}{}-bit source is rounded to 8-bit for `--output-depth 8`{N}",
+                inf.bit_depth
+            );
+        }
+        inf.out_8bit = true;
+    } else if inf.bit_depth > 10 {
+        eprintln!("{Y}{}-bit source is rounded to 10-bit for SVT-AV1{N}", inf.bit_depth);
+    }
+    inf.rc_qp = args.rc_qp;
 
     let mut args = args.clone();
-    if let Some(ref s) = args.crop_str {
-        args.crop = Some(if let Ok(ar) = s.parse::<f64>() {
-            let (cur_dim, new_exact, is_vert) = if ar > f64::from(inf.width) / f64::from(inf.height)
-            {
-                (inf.height, f64::from(inf.width) / ar, true)
-            } else {
-                (inf.width, f64::from(inf.height) * ar, false)
-            };
-
-            let mut new_dim = new_exact as u32;
-            let cur_mod4 = cur_dim % 4;
-            let new_mod4 = new_dim % 4;
-
-            if new_mod4 != cur_mod4 || new_exact.fract() != 0.0 {
-                let mut adj = (cur_mod4 + 4 - new_mod4) % 4;
-                if adj == 0 {
-                    adj = 4;
-                }
-                new_dim += adj;
-            }
-
-            let crop = ((cur_dim - new_dim) / 2) & !1;
-            if is_vert { (crop, 0) } else { (0, crop) }
-        } else {
-            let p: Vec<u32> = s.split(',').filter_map(|x| x.parse().ok()).collect();
-            if p.len() == 2 { (p[0] & !1, p[1] & !1) } else { (0, 0) }
-        });
+    resolve_crop(&mut args, &idx, &inf)?;
+    expand_output(&mut args, &inf);
+    check_output(&args)?;
+    if container == chunk::Container::Avif && inf.frames == 1 {
+        args.params = svt::set_param(&args.params, "--avif", "1");
+    }
+    if args.name.is_some()
+        && let Some(parent) = args.output.parent()
+    {
+        fs::create_dir_all(parent)?;
     }
 
     let grain_table = if let Some(iso) = args.noise {
         let table_path = work_dir.join("grain.tbl");
-        noise::gen_table(iso, &inf, &table_path)?;
+        if !args.resume || !table_path.exists() {
+            noise::gen_table(iso, args.seed, &inf, &table_path)?;
+        }
         Some(table_path)
     } else {
-        None
+        args.grain_table.clone()
+    };
+
+    let src_frames = inf.frames;
+    let (min_len, max_len) =
+        chunk::scene_limits(inf.fps_num, inf.fps_den, args.scene_len).map_err(XavError::Args)?;
+    let (chunks, scd) = if overlap {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let merge = args.merge.then_some((min_len, max_len));
+        let (input, scene_file, fast, split) =
+            (args.input.clone(), args.scene_file.clone(), args.scd_fast, args.split);
+        let (frames, scene_len, fps) = (inf.frames, args.scene_len, args.fps);
+        let handle = std::thread::spawn(move || {
+            let feed = std::cell::RefCell::new(chunk::SceneFeed::new(tx, merge, split));
+            let on_cut = |f| feed.borrow_mut().cut(f);
+            scd::fd_scenes(&input, &scene_file, true, fast, scene_len, fps, Some(&on_cut))
+                .map_err(|e| e.to_string())?;
+            Ok::<_, String>(feed.into_inner().finish(frames))
+        });
+        (Vec::new(), Some((rx, handle)))
+    } else {
+        (plan_chunks(&args, &mut inf, min_len, max_len)?, None)
     };
 
-    let scenes = chunk::load_scenes(&args.scene_file, inf.frames)?;
-    chunk::validate_scenes(&scenes, inf.fps_num, inf.fps_den)?;
+    let video_mkv = work_dir.join("encode").join("video.mkv");
+    let merged = scd.is_none()
+        && args.resume
+        && video_mkv.exists()
+        && chunk::get_resume(&work_dir).is_some_and(|r| r.chnks_done.len() == chunks.len());
+
+    if !merged {
+        for &kind in &args.dyn_meta {
+            let dir = kind.prepare(&args.input, &chunks, src_frames, &work_dir)?;
+            inf.dyn_meta.push((kind, dir));
+        }
+    }
 
-    let chunks = chunk::chunkify(&scenes);
+    let size_pred = if args.target_size.is_some() && !merged {
+        let (crf, pred) = size_crf(&args, &chunks, &inf, &idx, &work_dir, grain_table.as_deref())?;
+        args.params = svt::set_rate(&args.params, args.rc_qp, f64::from(crf));
+        Some(pred)
+    } else {
+        None
+    };
 
+    let (feed, scd) = scd.unzip();
     let enc_start = std::time::Instant::now();
-    svt::encode_all(&chunks, &inf, &args, &idx, &work_dir, grain_table.as_ref());
+    if !merged {
+        svt::encode_all(&chunks, feed, &inf, &args, &idx, &work_dir, grain_table.as_ref())?;
+    }
+    ENCODING.store(false, Ordering::Relaxed);
+    if STOP.load(Ordering::Relaxed) {
+        return Err(XavError::Interrupted.into());
+    }
     let enc_time = enc_start.elapsed();
 
-    let video_mkv = work_dir.join("encode").join("video.mkv");
-    chunk::merge_out(&work_dir.join("encode"), &video_mkv, &inf)?;
+    let chunks = match scd {
+        Some(h) => h.join().unwrap().map_err(XavError::Scenes)?,
+        None => chunks,
+    };
 
-    print!("\x1b[?25h\x1b[?1049l");
-    std::io::stdout().flush().unwrap();
+    let tags = if args.embed_settings {
+        let tags_path = work_dir.join("tags.xml");
+        write_tags(&args, &tags_path)?;
+        Some(tags_path)
+    } else {
+        None
+    };
+    let mux = chunk::MuxOpts {
+        container,
+        tags: tags.as_deref(),
+        chapters: args.chapters,
+        subs: args.subs.as_deref(),
+    };
+
+    if !merged {
+        chunk::verify_chunks(&work_dir.join("encode"), &chunks)?;
+        let part = work_dir.join("encode").join("video.part.mkv");
+        chunk::merge_out(&work_dir.join("encode"), &part, &inf, mux)?;
+        fs::rename(&part, &video_mkv)?;
+    }
+
+    progs::close_socket();
+    restore();
 
-    let input_size = fs::metadata(&args.input)?.len();
+    let input_size =
+        fs::metadata(&args.input)?.len() * inf.frames as u64 / src_frames.max(1) as u64;
     let output_size = fs::metadata(&video_mkv)?.len();
     let duration = inf.frames as f64 * f64::from(inf.fps_den) / f64::from(inf.fps_num);
     let input_br = (input_size as f64 * 8.0) / duration / 1000.0;
     let output_br = (output_size as f64 * 8.0) / duration / 1000.0;
     let change = ((output_size as f64 / input_size as f64) - 1.0) * 100.0;
 
+    let fps_rate = f64::from(inf.fps_num) / f64::from(inf.fps_den);
+    let enc_speed = inf.frames as f64 / enc_time.as_secs_f64().max(0.001);
+
+    let (final_width, final_height) =
+        args.crop.map_or((inf.width, inf.height), |c| c.dims(inf.width, inf.height));
+
     let fmt_size = |b: u64| {
         if b > 1_000_000_000 {
             format!("{:.2} GB", b as f64 / 1_000_000_000.0)
@@ -443,23 +2155,26 @@ fn main_with_args(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
     let arrow = if change < 0.0 { "󰛀" } else { "󰛃" };
     let change_color = if change < 0.0 { G } else { R };
 
-    let fps_rate = f64::from(inf.fps_num) / f64::from(inf.fps_den);
-    let enc_speed = inf.frames as f64 / enc_time.as_secs_f64();
-
     let enc_secs = enc_time.as_secs();
     let (eh, em, es) = (enc_secs / 3600, (enc_secs % 3600) / 60, enc_secs % 60);
 
     let dur_secs = duration as u64;
     let (dh, dm, ds) = (dur_secs / 3600, (dur_secs % 3600) / 60, dur_secs % 60);
 
-    let (final_width, final_height) = if let Some((crop_v, crop_h)) = args.crop {
-        (inf.width - crop_h * 2, inf.height - crop_v * 2)
+    if args.json {
+        println!(
+            "{{\"input\":\"{}\",\"output\":\"{}\",\"input_size\":{input_size},\"output_size\":\
+             {output_size},\"input_kbps\":{input_br:.2},\"output_kbps\":{output_br:.2},\"\
+             change_pct\":{change:.2},\"width\":{final_width},\"height\":{final_height},\"fps\":\
+             {fps_rate:.3},\"duration_secs\":{duration:.3},\"encode_secs\":{:.3},\"encode_fps\":\
+             {enc_speed:.2}}}",
+            json_str(&args.input.to_string_lossy()),
+            json_str(&args.output.to_string_lossy()),
+            enc_time.as_secs_f64()
+        );
     } else {
-        (inf.width, inf.height)
-    };
-
-    eprintln!(
-    "\n{P}┏━━━━━━━━━━━┳━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓\n\
+        eprintln!(
+        "\n{P}┏━━━━━━━━━━━┳━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓\n\
 {P}┃ {G}✅ {Y}DONE   {P}┃ {R}{:<30.30} {G}󰛂 {G}{:<30.30} {P}┃\n\
 {P}┣━━━━━━━━━━━╋━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┫\n\
 {P}┃ {Y}Size      {P}┃ {R}{:<98} {P}┃\n\
@@ -468,49 +2183,110 @@ fn main_with_args(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
 {P}┣━━━━━━━━━━━╋━━━━━━━━━━━┻━━━━━━━━━━━━┻━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┫\n\
 {P}┃ {Y}Time      {P}┃ {W}{:02}{C}:{W}{:02}{C}:{W}{:02} {B}@ {:>6.2} fps{:<42} {P}┃\n\
 {P}┗━━━━━━━━━━━┻━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛{N}",
-    args.input.file_name().unwrap().to_string_lossy(),
-    args.output.file_name().unwrap().to_string_lossy(),
-    format!("{} {C}({:.0} kb/s) {G}󰛂 {G}{} {C}({:.0} kb/s) {}{} {:.2}%", 
-        fmt_size(input_size), input_br, fmt_size(output_size), output_br, change_color, arrow, change.abs()),
-    final_width, final_height, fps_rate, dh, dm, ds, "",
-    eh, em, es, enc_speed, ""
-);
+        args.input.file_name().unwrap().to_string_lossy(),
+        args.output.file_name().unwrap().to_string_lossy(),
+        format!("{} {C}({:.0} kb/s) {G}󰛂 {G}{} {C}({:.0} kb/s) {}{} {:.2}%", 
+            fmt_size(input_size), input_br, fmt_size(output_size), output_br, change_color, arrow, change.abs()),
+        final_width, final_height, fps_rate, dh, dm, ds, "",
+        eh, em, es, enc_speed, ""
+        );
+        if let (Some(mb), Some(pred)) = (args.target_size, size_pred) {
+            eprintln!(
+                "{Y}Target size: {W}{} {C}predicted {W}{} {C}actual {W}{}{N}",
+                fmt_size((mb * 1_000_000.0) as u64),
+                fmt_size(pred),
+                fmt_size(output_size)
+            );
+        }
+    }
+
+    progs::print_warnings();
 
     if let Some(ref audio_spec) = args.audio {
-        audio::process_audio(audio_spec, &args.input, &video_mkv, &args.output)?;
-        fs::remove_file(&video_mkv)?;
+        audio::process_audio(audio_spec, &args.input, &video_mkv, &work_dir, &args.output, mux)
+            .map_err(|e| error::classify(e, XavError::Mux))?;
+    } else if container == chunk::Container::Avif {
+        audio::mux_avif(&video_mkv, &args.output).map_err(|e| error::classify(e, XavError::Mux))?;
+    } else if container == chunk::Container::Mp4 {
+        audio::mux_mp4(&video_mkv, &[], args.chapters.then_some(&args.input), &args.output)
+            .map_err(|e| error::classify(e, XavError::Mux))?;
+    } else if args.chapters {
+        chunk::mux_chapters(&video_mkv, &args.input, &args.output, mux)?;
+    } else if args.keep_workdir {
+        fs::copy(&video_mkv, &args.output)?;
     } else {
         fs::rename(&video_mkv, &args.output)?;
     }
 
-    fs::remove_dir_all(&work_dir)?;
+    if args.verify {
+        verify_output(&args, &inf)?;
+    }
+
+    if args.keep_meta {
+        save_meta(&args, &work_dir, &inf, grain_table.as_deref())?;
+    }
 
-    Ok(())
+    if args.keep_workdir {
+        eprintln!("{Y}Work dir kept: {W}{}{N}", work_dir.display());
+    } else {
+        fs::remove_dir_all(&work_dir)?;
+    }
+
+    Ok(args.output)
+}
+
+#[cfg(feature = "vship")]
+fn worst_mean(sorted: &[(f64, usize)], frames: usize) -> f64 {
+    let mut left = frames;
+    let mut sum = 0.0;
+    for &(score, weight) in sorted {
+        let take = weight.min(left);
+        sum += score * take as f64;
+        left -= take;
+        if left == 0 {
+            break;
+        }
+    }
+    sum / (frames - left) as f64
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = parse_args();
+fn main() {
+    let argv: Vec<String> = std::env::args().collect();
+    if argv.get(1).is_some_and(|a| a == "--resume-list") {
+        let dir = argv[2..].iter().find(|a| !a.starts_with('-')).map_or(".", String::as_str);
+        if let Err(e) = resume_list(Path::new(dir), argv[2..].iter().any(|a| a == "--json")) {
+            eprintln!("{e}");
+            std::process::exit(error::code_of(e.as_ref()));
+        }
+        return;
+    }
+
+    let mut args = parse_args();
     let output = args.output.clone();
 
     std::panic::set_hook(Box::new(move |panic_info| {
-        print!("\x1b[?25h\x1b[?1049l");
-        let _ = std::io::stdout().flush();
+        restore();
+        if PANICKED.swap(true, Ordering::SeqCst) {
+            return;
+        }
         eprintln!("{panic_info}");
         eprintln!("{}, FAIL", output.display());
+        std::process::exit(error::PANIC_CODE);
     }));
 
     unsafe {
         libc::atexit(restore);
+        libc::atexit(remove_spool);
         libc::signal(libc::SIGINT, exit_restore as usize);
         libc::signal(libc::SIGSEGV, exit_restore as usize);
     }
 
-    if let Err(e) = main_with_args(&args) {
-        print!("\x1b[?1049l");
-        std::io::stdout().flush().unwrap();
+    args.output = main_with_args(&args).unwrap_or_else(|e| {
+        restore();
+        eprintln!("{e}");
         eprintln!("{}, FAIL", args.output.display());
-        return Err(e);
-    }
+        std::process::exit(error::code_of(e.as_ref()));
+    });
 
     #[cfg(feature = "vship")]
     if args.target_quality.is_some()
@@ -525,25 +2301,59 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .split('-')
             .filter_map(|s| s.parse().ok())
             .collect();
-        let is_butteraugli = f64::midpoint(tq_parts[0], tq_parts[1]) < 8.0;
+        let target = f64::midpoint(tq_parts[0], tq_parts[1]);
+        let is_butteraugli = !args.xpsnr && target < 8.0;
+        let is_cvvdp = !args.xpsnr && target > 8.0 && target <= 10.0;
+
+        let metric = if args.xpsnr {
+            "XPSNR (higher is better, worst = lowest)"
+        } else if is_butteraugli {
+            "Butteraugli (lower is better, worst = highest)"
+        } else if is_cvvdp {
+            "CVVDP (higher is better, worst = lowest)"
+        } else {
+            "SSIMULACRA2 (higher is better, worst = lowest)"
+        };
 
         if is_butteraugli {
-            s.sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
+            s.sort_unstable_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
         } else {
-            s.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+            s.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
         }
 
-        let m = s.iter().sum::<f64>() / s.len() as f64;
-        eprintln!("\n{Y}Mean: {W}{m:.4}");
+        let frames: usize = s.iter().map(|&(_, w)| w).sum();
+        let m = worst_mean(&s, frames);
+        let mut rows = vec![("Mean".to_string(), m)];
         for p in [25.0, 10.0, 5.0, 1.0, 0.1] {
-            let i = ((s.len() as f64 * p / 100.0).ceil() as usize).min(s.len());
-            eprintln!("{Y}Mean of worst {p}%: {W}{:.4}", s[..i].iter().sum::<f64>() / i as f64);
+            let n = ((frames as f64 * p / 100.0).ceil() as usize).clamp(1, frames);
+            rows.push((format!("Mean of worst {p}%"), worst_mean(&s, n)));
+        }
+        rows.push((
+            "STDDEV".to_string(),
+            (s.iter().map(|&(x, w)| (x - m).powi(2) * w as f64).sum::<f64>() / frames as f64)
+                .sqrt(),
+        ));
+
+        eprintln!();
+        for (name, v) in &rows {
+            eprintln!("{Y}{name}: {W}{v:.4}{N}");
         }
-        eprintln!(
-            "{Y}STDDEV: {W}{:.4}{N}",
-            (s.iter().map(|&x| (x - m).powi(2)).sum::<f64>() / s.len() as f64).sqrt()
-        );
-    }
 
-    Ok(())
+        let mut txt = format!("Metric: {metric}\nFrames: {frames}\n");
+        if is_cvvdp {
+            txt.push_str(&format!(
+                "Chunks: {} (CVVDP scores whole chunks, each counts once per frame)\n",
+                s.len()
+            ));
+        }
+        for (name, v) in &rows {
+            txt.push_str(&format!("{name}: {v:.4}\n"));
+        }
+
+        let stem = args.output.file_stem().unwrap().to_string_lossy();
+        let path = args.output.with_file_name(format!("{stem}_scores.txt"));
+        if let Err(e) = fs::write(&path, txt) {
+            eprintln!("Cannot write {}: {e}", path.display());
+        }
+    }
 }
@@ -4,7 +4,12 @@ use av1_grain::{NoiseGenArgs, TransferFunction, generate_photon_noise_params, wr
 
 use crate::ffms::VidInf;
 
-pub fn gen_table(iso: u32, inf: &VidInf, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+pub fn gen_table(
+    iso: u32,
+    seed: Option<u64>,
+    inf: &VidInf,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
     let transfer = if inf.transfer_characteristics == Some(16) {
         TransferFunction::SMPTE2084
     } else {
@@ -17,7 +22,11 @@ pub fn gen_table(iso: u32, inf: &VidInf, output: &Path) -> Result<(), Box<dyn st
         height: inf.height,
         transfer_function: transfer,
         chroma_grain: true,
-        random_seed: None,
+        random_seed: seed.map(|s| {
+            let mut bytes = [0u8; 32];
+            bytes[..8].copy_from_slice(&s.to_le_bytes());
+            bytes
+        }),
     };
 
     let duration = inf.frames as u64 * u64::from(inf.fps_den) * 10_000_000 / u64::from(inf.fps_num);
@@ -26,3 +35,12 @@ pub fn gen_table(iso: u32, inf: &VidInf, output: &Path) -> Result<(), Box<dyn st
     write_grain_table(output, &[segment])?;
     Ok(())
 }
+
+pub fn check_table(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)
+        .map_err(|e| format!("Cannot read grain table {}: {e}", path.display()))?;
+    if !data.starts_with(b"filmgrn1") {
+        return Err(format!("{} is not a film grain table", path.display()).into());
+    }
+    Ok(())
+}
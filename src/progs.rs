@@ -1,13 +1,25 @@
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Write};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 static DISPLAY_MUTEX: Mutex<()> = Mutex::new(());
+static ENC_WARNINGS: Mutex<BTreeMap<String, BTreeSet<usize>>> = Mutex::new(BTreeMap::new());
+static SOCKET_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+static SOCKET_CLIENTS: Mutex<Vec<UnixStream>> = Mutex::new(Vec::new());
 
 const BAR_WIDTH: usize = 32;
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+const SOCKET_INTERVAL: Duration = Duration::from_secs(1);
+const SOCKET_TIMEOUT: Duration = Duration::from_millis(100);
+const OUTLIER_FACTOR: f32 = 3.0;
+const MAX_OUTLIERS: usize = 5;
+const OUTLIER_MIN_CHUNKS: usize = 8;
 
 const G: &str = "\x1b[1;92m";
 const R: &str = "\x1b[1;91m";
@@ -21,6 +33,94 @@ const N: &str = "\x1b[0m";
 const G_HASH: &str = "\x1b[1;92m#";
 const R_DASH: &str = "\x1b[1;91m-";
 
+fn note_warnings(text: &str, chunk_idx: usize) {
+    for line in text.lines().map(str::trim) {
+        if line.to_ascii_lowercase().contains("warn") {
+            ENC_WARNINGS.lock().unwrap().entry(line.to_string()).or_default().insert(chunk_idx);
+        }
+    }
+}
+
+pub fn watch_warnings(stderr: impl Read + Send + 'static, chunk_idx: usize) {
+    thread::spawn(move || {
+        let mut text = Vec::new();
+        let _ = BufReader::new(stderr).read_to_end(&mut text);
+        note_warnings(&String::from_utf8_lossy(&text), chunk_idx);
+    });
+}
+
+pub fn print_warnings() {
+    let warnings = ENC_WARNINGS.lock().unwrap();
+    if warnings.is_empty() {
+        return;
+    }
+    eprintln!("{Y}Encoder warnings:{N}");
+    for (line, chunks) in warnings.iter() {
+        let first: Vec<_> = chunks.iter().take(5).map(|i| format!("{i:04}")).collect();
+        let more = chunks.len().saturating_sub(first.len());
+        let more = if more > 0 { format!(" +{more}") } else { String::new() };
+        eprintln!("{W}{line} {C}(chunks {}{more}){N}", first.join(" "));
+    }
+}
+
+pub fn open_socket(path: &Path) -> std::io::Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    *SOCKET_PATH.lock().unwrap() = Some(path.to_path_buf());
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_write_timeout(Some(SOCKET_TIMEOUT));
+            SOCKET_CLIENTS.lock().unwrap().push(stream);
+        }
+    });
+    Ok(())
+}
+
+pub fn close_socket() {
+    let Some(path) = SOCKET_PATH.lock().unwrap().take() else {
+        return;
+    };
+    emit(|| r#"{"event":"done"}"#.to_string());
+    SOCKET_CLIENTS.lock().unwrap().clear();
+    let _ = std::fs::remove_file(path);
+}
+
+fn emit(event: impl FnOnce() -> String) {
+    let mut clients = SOCKET_CLIENTS.lock().unwrap();
+    if clients.is_empty() {
+        return;
+    }
+    let line = event() + "\n";
+    clients.retain_mut(|c| c.write_all(line.as_bytes()).is_ok());
+}
+
+fn json_num(v: Option<f64>) -> String {
+    v.map_or_else(|| "null".to_string(), |v| format!("{v:.2}"))
+}
+
+pub fn chunk_start(chunk_idx: usize, crf_score: Option<(f32, Option<f64>)>) {
+    emit(|| {
+        let (crf, score) = crf_score.map_or((None, None), |(c, s)| (Some(f64::from(c)), s));
+        format!(
+            r#"{{"event":"chunk_start","chunk":{chunk_idx},"crf":{},"score":{}}}"#,
+            json_num(crf),
+            json_num(score)
+        )
+    });
+}
+
+pub fn chunk_done(comp: &crate::chunk::ChunkComp) {
+    emit(|| {
+        format!(
+            r#"{{"event":"chunk_done","chunk":{},"frames":{},"size":{}}}"#,
+            comp.idx, comp.frames, comp.size
+        )
+    });
+}
+
 pub struct ProgsBar {
     s_time: Instant,
     last_up: Instant,
@@ -71,6 +171,14 @@ impl ProgsBar {
     }
 
     pub fn up_scenes(&mut self, current: usize, tot: usize) {
+        self.up_frames("SCD", current, tot);
+    }
+
+    pub fn up_verify(&mut self, current: usize, tot: usize) {
+        self.up_frames("CHK", current, tot);
+    }
+
+    fn up_frames(&mut self, label: &str, current: usize, tot: usize) {
         if self.quiet {
             return;
         }
@@ -91,7 +199,7 @@ impl ProgsBar {
         let perc = (current * 100 / tot.max(1)).min(100);
 
         print!(
-            "\r\x1b[2K{W}SCD: {C}[{bar}{C}] {W}{perc}%{C}, {Y}{fps} FPS{C}, {W}{eta_str}{C}, \
+            "\r\x1b[2K{W}{label}: {C}[{bar}{C}] {W}{perc}%{C}, {Y}{fps} FPS{C}, {W}{eta_str}{C}, \
              {G}{current}{C}/{R}{tot}{N}"
         );
         std::io::stdout().flush().unwrap();
@@ -129,6 +237,7 @@ struct ProgsState {
     completions: Arc<Mutex<crate::chunk::ResumeInf>>,
     fps_num: usize,
     fps_den: usize,
+    chunk_frames: Vec<(usize, usize)>,
 }
 
 pub struct ProgsTrack {
@@ -162,6 +271,7 @@ impl ProgsTrack {
                 completions,
                 fps_num: inf.fps_num as usize,
                 fps_den: inf.fps_den as usize,
+                chunk_frames: chunks.iter().map(|c| (c.idx, c.end - c.start)).collect(),
             }),
         }
     }
@@ -200,6 +310,7 @@ impl ProgsTrack {
                 }
 
                 if !line.contains("Encoding:") || line.contains("SUMMARY") {
+                    note_warnings(line, chunk_idx);
                     continue;
                 }
 
@@ -265,17 +376,36 @@ impl ProgsTrack {
         }
     }
 
+    fn remaining_frames(
+        state: &ProgsState,
+        lines: &HashMap<usize, String>,
+        done: &HashSet<usize>,
+    ) -> (usize, usize) {
+        state.chunk_frames.iter().filter(|(idx, _)| !done.contains(idx)).fold(
+            (0, 0),
+            |(sum, longest), &(idx, len)| {
+                let cur = lines.get(&idx).and_then(|l| Self::get_frame_cnt(l)).unwrap_or(0);
+                let left = len.saturating_sub(cur);
+                (sum + left, longest.max(left))
+            },
+        )
+    }
+
     fn show_progs(
         lines: &Arc<Mutex<HashMap<usize, String>>>,
         processed: &Arc<AtomicUsize>,
         state: &Arc<ProgsState>,
     ) {
         let _guard = DISPLAY_MUTEX.lock().unwrap();
+        if crate::PANICKED.load(Ordering::Relaxed) {
+            return;
+        }
 
         let processed_frames = processed.load(Ordering::Relaxed);
 
         let data = state.completions.lock().unwrap();
         let completed_frames: usize = data.chnks_done.iter().map(|c| c.frames).sum();
+        let done: HashSet<usize> = data.chnks_done.iter().map(|c| c.idx).collect();
         drop(data);
 
         let frames_done =
@@ -287,10 +417,16 @@ impl ProgsTrack {
         let elapsed_secs = elapsed.as_secs() as usize;
         let fps = new_frames as f32 / elapsed_secs.max(1) as f32;
 
-        let remaining = state.tot_frames.saturating_sub(frames_done);
+        let remaining = if state.chunk_frames.is_empty() {
+            state.tot_frames.saturating_sub(frames_done)
+        } else {
+            let (left, longest) = Self::remaining_frames(state, &lines.lock().unwrap(), &done);
+            left.max(longest * state.worker_cnt)
+        };
         let eta_secs = remaining * elapsed_secs / new_frames.max(1);
 
         let chunks_done = state.completed.load(Ordering::Relaxed);
+        let tot_chunks = fmt_total(state.tot_chunks);
         let (bitrate_str, est_str) = get_bitrate_estimates(state);
 
         print!("\x1b[u");
@@ -313,12 +449,13 @@ impl ProgsTrack {
         let bar = format!("{}{}", G_HASH.repeat(progs), R_DASH.repeat(BAR_WIDTH - progs));
 
         println!(
-            "{W}{h:02}{P}:{W}{m:02}{P}:{W}{s:02} {C}[{G}{chunks_done}{C}/{R}{}{C}] [{bar}{C}] \
-             {W}{perc}% {G}{frames_done}{C}/{R}{} {C}({Y}{fps:.2} FPS{C}, \
+            "{W}{h:02}{P}:{W}{m:02}{P}:{W}{s:02} {C}[{G}{chunks_done}{C}/{R}{tot_chunks}{C}] \
+             [{bar}{C}] {W}{perc}% {G}{frames_done}{C}/{R}{} {C}({Y}{fps:.2} FPS{C}, \
              {W}{eta_h:02}{P}:{W}{eta_m:02}{P}:{W}{eta_s:02}{C}, {bitrate_str}{C}, \
              {R}{est_str}{C}){N}",
-            state.tot_chunks, state.tot_frames
+            state.tot_frames
         );
+        println!("\r\x1b[2K{}", get_outliers(state));
 
         std::io::stdout().flush().unwrap();
     }
@@ -361,6 +498,96 @@ impl ProgsTrack {
     }
 }
 
+pub struct ProgsLog {
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl ProgsLog {
+    pub fn start(
+        path: Option<&Path>,
+        tot_chunks: usize,
+        tot_frames: usize,
+        init_frames: usize,
+        completed: Arc<AtomicUsize>,
+        frames_done: Arc<AtomicUsize>,
+    ) -> std::io::Result<Self> {
+        let mut file = path
+            .map(|p| std::fs::OpenOptions::new().create(true).append(true).open(p))
+            .transpose()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let (mut last_log, mut last_emit) = (start, start);
+
+            loop {
+                let done = flag.load(Ordering::Relaxed);
+                let frames = frames_done.load(Ordering::Relaxed);
+                let chunks = completed.load(Ordering::Relaxed);
+                let elapsed = start.elapsed().as_secs().max(1) as usize;
+                let new_frames = frames.saturating_sub(init_frames);
+                let fps = new_frames as f32 / elapsed as f32;
+                let eta = tot_frames.saturating_sub(frames) * elapsed / new_frames.max(1);
+
+                if let Some(f) = file.as_mut()
+                    && (done || last_log.elapsed() >= LOG_INTERVAL)
+                {
+                    last_log = Instant::now();
+                    let perc = (frames * 100 / tot_frames.max(1)).min(100);
+                    let _ = writeln!(
+                        f,
+                        "{} frames {frames}/{tot_frames} ({perc}%), chunks {chunks}/{}, {fps:.2} \
+                         fps, ETA {}",
+                        fmt_dur(Duration::from_secs(elapsed as u64)),
+                        fmt_total(tot_chunks),
+                        fmt_dur(Duration::from_secs(eta as u64))
+                    );
+                }
+                if done || last_emit.elapsed() >= SOCKET_INTERVAL {
+                    last_emit = Instant::now();
+                    emit(|| {
+                        let eta =
+                            if tot_frames == 0 { "null".to_string() } else { eta.to_string() };
+                        format!(
+                            r#"{{"event":"progress","frames":{frames},"total_frames":{},"chunks":{chunks},"total_chunks":{},"fps":{fps:.2},"eta_secs":{eta}}}"#,
+                            json_total(tot_frames),
+                            json_total(tot_chunks)
+                        )
+                    });
+                }
+                if done {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(250));
+            }
+        });
+
+        Ok(Self { stop, handle: Some(handle) })
+    }
+
+    pub fn finish(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+fn fmt_dur(d: Duration) -> String {
+    let tot_secs = d.as_secs();
+    format!("{:02}:{:02}:{:02}", tot_secs / 3600, (tot_secs % 3600) / 60, tot_secs % 60)
+}
+
+fn fmt_total(n: usize) -> String {
+    if n == 0 { "?".to_string() } else { n.to_string() }
+}
+
+fn json_total(n: usize) -> String {
+    if n == 0 { "null".to_string() } else { n.to_string() }
+}
+
 fn get_bitrate_estimates(state: &ProgsState) -> (String, String) {
     let data = state.completions.lock().unwrap();
     let tot_size: u64 = data.chnks_done.iter().map(|c| c.size).sum();
@@ -382,6 +609,40 @@ fn get_bitrate_estimates(state: &ProgsState) -> (String, String) {
     (format!("{B}{bitrate_kbps:.0} kb{C}/{B}s"), format!("{R}{est_str}"))
 }
 
+fn get_outliers(state: &ProgsState) -> String {
+    let data = state.completions.lock().unwrap();
+    let mut rates: Vec<(usize, f32)> = data
+        .chnks_done
+        .iter()
+        .map(|c| {
+            let secs = c.frames.max(1) as f32 * state.fps_den as f32 / state.fps_num as f32;
+            (c.idx, c.size as f32 * 8.0 / secs / 1000.0)
+        })
+        .collect();
+    drop(data);
+
+    if rates.len() < OUTLIER_MIN_CHUNKS {
+        return String::new();
+    }
+
+    rates.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+    let median = rates[rates.len() / 2].1;
+    let outliers: Vec<String> = rates
+        .iter()
+        .take(MAX_OUTLIERS)
+        .take_while(|(_, kbps)| *kbps > median * OUTLIER_FACTOR)
+        .map(|(idx, kbps)| format!("{W}{idx:04} {B}{kbps:.0} kb{C}/{B}s"))
+        .collect();
+
+    if outliers.is_empty() {
+        return String::new();
+    }
+    format!(
+        "{Y}Heavy chunks {C}(>{OUTLIER_FACTOR}x median {B}{median:.0} kb{C}/{B}s{C}): {}{N}",
+        outliers.join(&format!("{C}, "))
+    )
+}
+
 fn fmt_dur_colored(d: Duration) -> String {
     let tot_secs = d.as_secs();
     let hours = tot_secs / 3600;
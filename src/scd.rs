@@ -1,57 +1,107 @@
+use std::cell::{Cell, RefCell};
 use std::fmt::Write;
 use std::fs;
+use std::io::Write as _;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 use av_scenechange::{DetectionOptions, SceneDetectionSpeed, av_decoders, detect_scene_changes};
 
+use crate::chunk::SceneLen;
 use crate::ffms;
 use crate::progs::ProgsBar;
 
+fn saved_cuts(part: &Path, header: &str) -> Vec<usize> {
+    let Ok(text) = fs::read_to_string(part) else { return Vec::new() };
+    let Some(body) = text.strip_prefix(header).and_then(|t| t.strip_prefix('\n')) else {
+        return Vec::new();
+    };
+    let done = body.rfind('\n').map_or("", |i| &body[..i]);
+    let cuts: Vec<usize> = done.lines().filter_map(|l| l.trim().parse().ok()).collect();
+    if cuts.first() == Some(&0) && cuts.is_sorted() { cuts } else { Vec::new() }
+}
+
 pub fn fd_scenes(
     vid_path: &Path,
     scene_file: &Path,
     quiet: bool,
+    fast: bool,
+    scene_len: (Option<SceneLen>, Option<SceneLen>),
+    fps: Option<(u32, u32)>,
+    on_cut: Option<&dyn Fn(usize)>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let idx = ffms::VidIdx::new(vid_path, quiet)?;
     let inf = ffms::get_vidinf(&idx)?;
 
-    let min_dist = (inf.fps_num + inf.fps_den / 2) / inf.fps_den;
-    let max_dist = ((inf.fps_num * 10 + inf.fps_den / 2) / inf.fps_den).min(300);
+    let (fps_num, fps_den) = fps.unwrap_or((inf.fps_num, inf.fps_den));
+    let (min_dist, max_dist) = crate::chunk::scene_limits(fps_num, fps_den, scene_len)?;
     let tot_frames = inf.frames;
     drop(idx);
 
     let mut decoder = av_decoders::Decoder::from_file(vid_path)?;
 
     let opts = DetectionOptions {
-        analysis_speed: SceneDetectionSpeed::Standard,
+        analysis_speed: if fast {
+            SceneDetectionSpeed::Fast
+        } else {
+            SceneDetectionSpeed::Standard
+        },
         detect_flashes: false,
-        min_scenecut_distance: Some(min_dist as usize),
-        max_scenecut_distance: Some(max_dist as usize),
+        min_scenecut_distance: Some(min_dist),
+        max_scenecut_distance: Some(max_dist),
         lookahead_distance: 1,
     };
 
+    let mut part = scene_file.as_os_str().to_owned();
+    part.push(".part");
+    let header = format!("# {} {min_dist} {max_dist} {fast}", crate::input_fingerprint(vid_path)?);
+    let saved = if on_cut.is_some() { saved_cuts(Path::new(&part), &header) } else { Vec::new() };
+    let log = if saved.is_empty() {
+        let mut file = fs::File::create(&part)?;
+        writeln!(file, "{header}\n0")?;
+        file
+    } else {
+        if !quiet {
+            eprintln!(
+                "Encoding {} scene cuts from the interrupted detection while it restarts",
+                saved.len() - 1
+            );
+        }
+        fs::OpenOptions::new().append(true).open(&part)?
+    };
+    let log = RefCell::new(log);
+    let reused = saved.last().copied().unwrap_or(0);
+    if let Some(f) = on_cut {
+        saved.iter().skip(1).for_each(|&c| f(c));
+    }
+
     let progs = if quiet { None } else { Some(Arc::new(Mutex::new(ProgsBar::new(false)))) };
 
-    let results = if let Some(p) = &progs {
-        let progs_callback = {
-            let progs_clone = Arc::clone(p);
-            move |current: usize, _keyframes: usize| {
-                if let Ok(mut pb) = progs_clone.lock() {
-                    pb.up_scenes(current, tot_frames);
-                }
+    let found = Cell::new(1);
+    let cuts = RefCell::new(if saved.is_empty() { vec![0] } else { saved });
+    let callback = |current: usize, keyframes: usize| {
+        if let Some(p) = &progs
+            && let Ok(mut pb) = p.lock()
+        {
+            pb.up_scenes(current, tot_frames);
+        }
+        if keyframes > found.get() {
+            found.set(keyframes);
+            if current - 1 <= reused {
+                return;
+            }
+            cuts.borrow_mut().push(current - 1);
+            let _ = writeln!(log.borrow_mut(), "{}", current - 1);
+            if let Some(f) = on_cut {
+                f(current - 1);
             }
-        };
-
-        if inf.is_10bit {
-            detect_scene_changes::<u16>(&mut decoder, opts, None, Some(&progs_callback))?
-        } else {
-            detect_scene_changes::<u8>(&mut decoder, opts, None, Some(&progs_callback))?
         }
-    } else if inf.is_10bit {
-        detect_scene_changes::<u16>(&mut decoder, opts, None, None)?
+    };
+
+    let results = if inf.is_10bit() {
+        detect_scene_changes::<u16>(&mut decoder, opts, None, Some(&callback))?
     } else {
-        detect_scene_changes::<u8>(&mut decoder, opts, None, None)?
+        detect_scene_changes::<u8>(&mut decoder, opts, None, Some(&callback))?
     };
 
     if let Some(p) = progs
@@ -60,11 +110,13 @@ pub fn fd_scenes(
         pb.finish_scenes();
     }
 
+    let scene_changes = if on_cut.is_some() { cuts.into_inner() } else { results.scene_changes };
     let mut content = String::new();
-    for &scene_frame in &results.scene_changes {
+    for &scene_frame in &scene_changes {
         writeln!(content, "{scene_frame}").unwrap();
     }
 
     fs::write(scene_file, content)?;
+    let _ = fs::remove_file(&part);
     Ok(())
 }
@@ -0,0 +1,400 @@
+const WEIGHTS: [f64; 108] = [
+    0.0,
+    0.0007376606707406586,
+    0.0,
+    0.0,
+    0.0007793481682867309,
+    0.0,
+    0.0,
+    0.0004371155730107379,
+    0.0,
+    1.1041726426657346,
+    0.00066284834129271,
+    0.00015231632783718752,
+    0.0,
+    0.0016406437456599754,
+    0.0,
+    1.8422455520539298,
+    11.441172603757666,
+    0.0,
+    0.0007989109436015163,
+    0.000176816438078653,
+    0.0,
+    1.8787594979546387,
+    10.94906990605142,
+    0.0,
+    0.0007289346991508072,
+    0.9677937080626833,
+    0.0,
+    0.00014003424285435884,
+    0.9981766977854967,
+    0.00031949755934435053,
+    0.0004550992113792063,
+    0.0,
+    0.0,
+    0.0013648766163243398,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    7.466890328078848,
+    0.0,
+    17.445833984131262,
+    0.0006235601634041466,
+    0.0,
+    0.0,
+    6.683678146179332,
+    0.00037724407979611296,
+    1.027889937768264,
+    225.20515300849274,
+    0.0,
+    0.0,
+    19.213238186143016,
+    0.0011401524586618361,
+    0.001237755635509985,
+    176.39317598450694,
+    0.0,
+    0.0,
+    24.43300999870476,
+    0.28520802612117757,
+    0.0004485436923833408,
+    0.0,
+    0.0,
+    0.0,
+    34.77906344483772,
+    44.835625328877896,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0008680556573291698,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0005313191874358747,
+    0.0,
+    0.00016533814161379112,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0004179171803251336,
+    0.0017290828234722833,
+    0.0,
+    0.0020827005846636437,
+    0.0,
+    0.0,
+    8.826982764996862,
+    23.19243343998926,
+    0.0,
+    95.1080498811086,
+    0.9863978034400682,
+    0.9834382792465353,
+    0.0012286405048278493,
+    171.2667255897307,
+    0.9807858872435379,
+    0.0,
+    0.0,
+    0.0,
+    0.0005821211155060366,
+    0.0,
+    0.0,
+];
+
+const BIAS: f32 = 0.003_793_073_3;
+const C2: f32 = 0.0009;
+const SIGMA: f32 = 1.5;
+const RADIUS: usize = 5;
+
+struct Img {
+    w: usize,
+    h: usize,
+    c: [Vec<f32>; 3],
+}
+
+impl Img {
+    fn new(w: usize, h: usize) -> Self {
+        Self { w, h, c: [vec![0.0; w * h], vec![0.0; w * h], vec![0.0; w * h]] }
+    }
+}
+
+pub struct Ssimu2Cpu {
+    width: usize,
+    height: usize,
+    src_10bit: bool,
+    kr: f32,
+    kb: f32,
+    full_range: bool,
+    kernel: Vec<f32>,
+}
+
+impl Ssimu2Cpu {
+    pub fn new(
+        width: u32,
+        height: u32,
+        src_10bit: bool,
+        matrix: Option<i32>,
+        color_range: Option<i32>,
+    ) -> Self {
+        let (kr, kb) = match matrix {
+            Some(5 | 6) => (0.299, 0.114),
+            Some(9 | 10) => (0.2627, 0.0593),
+            _ => (0.2126, 0.0722),
+        };
+
+        let raw: Vec<f32> = (0..=2 * RADIUS)
+            .map(|i| {
+                let d = i as f32 - RADIUS as f32;
+                (-d * d / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+        let sum: f32 = raw.iter().sum();
+        let kernel = raw.iter().map(|k| k / sum).collect();
+
+        Self {
+            width: width as usize,
+            height: height as usize,
+            src_10bit,
+            kr,
+            kb,
+            full_range: color_range == Some(1),
+            kernel,
+        }
+    }
+
+    pub fn compute(
+        &self,
+        planes1: [*const u8; 3],
+        planes2: [*const u8; 3],
+        line_sizes1: [i64; 3],
+        line_sizes2: [i64; 3],
+    ) -> f64 {
+        let mut img1 = self.to_linear(planes1, line_sizes1, self.src_10bit);
+        let mut img2 = self.to_linear(planes2, line_sizes2, true);
+
+        let mut scales = Vec::with_capacity(6);
+
+        for scale in 0..6 {
+            if scale > 0 {
+                img1 = downsample(&img1);
+                img2 = downsample(&img2);
+            }
+            if img1.w < 8 || img1.h < 8 {
+                break;
+            }
+
+            let x1 = xyb(&img1);
+            let x2 = xyb(&img2);
+            let mut s = [0.0; 6];
+            let mut e = [0.0; 12];
+
+            for c in 0..3 {
+                let a = &x1.c[c];
+                let b = &x2.c[c];
+                let mu1 = self.blur(a, x1.w, x1.h);
+                let mu2 = self.blur(b, x1.w, x1.h);
+                let s11 = self.blur(&mul(a, a), x1.w, x1.h);
+                let s22 = self.blur(&mul(b, b), x1.w, x1.h);
+                let s12 = self.blur(&mul(a, b), x1.w, x1.h);
+
+                let n = a.len() as f64;
+                let (mut d1, mut d4) = (0.0, 0.0);
+                let (mut a1, mut a4, mut l1, mut l4) = (0.0, 0.0, 0.0, 0.0);
+
+                for i in 0..a.len() {
+                    let mu11 = mu1[i] * mu1[i];
+                    let mu22 = mu2[i] * mu2[i];
+                    let mu12 = mu1[i] * mu2[i];
+                    let num_m = 1.0 - (mu1[i] - mu2[i]).powi(2);
+                    let num_s = 2.0 * (s12[i] - mu12) + C2;
+                    let denom = (s11[i] - mu11) + (s22[i] - mu22) + C2;
+                    let d = f64::from((1.0 - num_m * num_s / denom).max(0.0));
+                    d1 += d;
+                    d4 += d.powi(4);
+
+                    let diff = (1.0 + (b[i] - mu2[i]).abs()) / (1.0 + (a[i] - mu1[i]).abs()) - 1.0;
+                    let art = f64::from(diff.max(0.0));
+                    let lost = f64::from((-diff).max(0.0));
+                    a1 += art;
+                    a4 += art.powi(4);
+                    l1 += lost;
+                    l4 += lost.powi(4);
+                }
+
+                s[c * 2] = d1 / n;
+                s[c * 2 + 1] = (d4 / n).powf(0.25);
+                e[c * 4] = a1 / n;
+                e[c * 4 + 1] = (a4 / n).powf(0.25);
+                e[c * 4 + 2] = l1 / n;
+                e[c * 4 + 3] = (l4 / n).powf(0.25);
+            }
+
+            scales.push((s, e));
+        }
+
+        let mut score = 0.0;
+        let mut i = 0;
+        for c in 0..3 {
+            for scale in 0..6 {
+                for n in 0..2 {
+                    if let Some((s, e)) = scales.get(scale) {
+                        score += WEIGHTS[i] * s[c * 2 + n].abs();
+                        score += WEIGHTS[i + 1] * e[c * 4 + n].abs();
+                        score += WEIGHTS[i + 2] * e[c * 4 + n + 2].abs();
+                    }
+                    i += 3;
+                }
+            }
+        }
+
+        score *= 0.956_238_261_683_484_4;
+        score = 2.326_765_642_916_932 * score - 0.020_884_521_182_843_837 * score.powi(2)
+            + 6.248_496_625_763_138e-5 * score.powi(3);
+        if score > 0.0 { 100.0 - 10.0 * score.powf(0.627_633_646_783_138_7) } else { 100.0 }
+    }
+
+    fn to_linear(&self, planes: [*const u8; 3], strides: [i64; 3], high_bit: bool) -> Img {
+        let (w, h) = (self.width, self.height);
+        let bps = if high_bit { 2 } else { 1 };
+        let rows = [h, h / 2, h / 2];
+        let cols = [w, w / 2, w / 2];
+        let data: Vec<&[u8]> = (0..3)
+            .map(|p| unsafe {
+                let len = strides[p] as usize * (rows[p] - 1) + cols[p] * bps;
+                std::slice::from_raw_parts(planes[p], len)
+            })
+            .collect();
+        let sample = |p: usize, x: usize, y: usize| {
+            let i = y * strides[p] as usize + x * bps;
+            if high_bit {
+                f32::from(u16::from_le_bytes([data[p][i], data[p][i + 1]]))
+            } else {
+                f32::from(data[p][i])
+            }
+        };
+
+        let s = if high_bit { 4.0 } else { 1.0 };
+        let max = if high_bit { 1023.0 } else { 255.0 };
+        let kg = 1.0 - self.kr - self.kb;
+        let mut img = Img::new(w, h);
+
+        for y in 0..h {
+            for x in 0..w {
+                let (cx, cy) = ((x / 2).min(cols[1] - 1), (y / 2).min(rows[1] - 1));
+                let (luma, cb, cr) = if self.full_range {
+                    (
+                        sample(0, x, y) / max,
+                        (sample(1, cx, cy) - 128.0 * s) / max,
+                        (sample(2, cx, cy) - 128.0 * s) / max,
+                    )
+                } else {
+                    (
+                        (sample(0, x, y) / s - 16.0) / 219.0,
+                        (sample(1, cx, cy) / s - 128.0) / 224.0,
+                        (sample(2, cx, cy) / s - 128.0) / 224.0,
+                    )
+                };
+
+                let r = luma + 2.0 * (1.0 - self.kr) * cr;
+                let b = luma + 2.0 * (1.0 - self.kb) * cb;
+                let g = (luma - self.kr * r - self.kb * b) / kg;
+
+                let i = y * w + x;
+                img.c[0][i] = eotf(r);
+                img.c[1][i] = eotf(g);
+                img.c[2][i] = eotf(b);
+            }
+        }
+
+        img
+    }
+
+    fn blur(&self, src: &[f32], w: usize, h: usize) -> Vec<f32> {
+        let mut tmp = vec![0.0; w * h];
+        let mut out = vec![0.0; w * h];
+
+        for y in 0..h {
+            let row = &src[y * w..(y + 1) * w];
+            for x in 0..w {
+                tmp[y * w + x] = self
+                    .kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, v)| v * row[(x + k).saturating_sub(RADIUS).min(w - 1)])
+                    .sum();
+            }
+        }
+
+        for y in 0..h {
+            for x in 0..w {
+                out[y * w + x] = self
+                    .kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, v)| v * tmp[(y + k).saturating_sub(RADIUS).min(h - 1) * w + x])
+                    .sum();
+            }
+        }
+
+        out
+    }
+}
+
+fn eotf(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.040_45 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn mul(a: &[f32], b: &[f32]) -> Vec<f32> {
+    a.iter().zip(b).map(|(x, y)| x * y).collect()
+}
+
+fn downsample(img: &Img) -> Img {
+    let (w, h) = (img.w.div_ceil(2), img.h.div_ceil(2));
+    let mut out = Img::new(w, h);
+
+    for c in 0..3 {
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = 0.0;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(img.w - 1);
+                    let sy = (y * 2 + dy).min(img.h - 1);
+                    sum += img.c[c][sy * img.w + sx];
+                }
+                out.c[c][y * w + x] = sum * 0.25;
+            }
+        }
+    }
+
+    out
+}
+
+fn xyb(img: &Img) -> Img {
+    let cb = BIAS.cbrt();
+    let mut out = Img::new(img.w, img.h);
+
+    for i in 0..img.w * img.h {
+        let (r, g, b) = (img.c[0][i], img.c[1][i], img.c[2][i]);
+        let m0 = (0.30 * r + 0.622 * g + 0.078 * b + BIAS).max(0.0).cbrt() - cb;
+        let m1 = (0.23 * r + 0.692 * g + 0.078 * b + BIAS).max(0.0).cbrt() - cb;
+        let m2 =
+            (0.243_422_69 * r + 0.204_767_44 * g + 0.551_809_87 * b + BIAS).max(0.0).cbrt() - cb;
+
+        let x = 0.5 * (m0 - m1);
+        let y = 0.5 * (m0 + m1);
+        out.c[0][i] = x.mul_add(14.0, 0.42);
+        out.c[1][i] = y + 0.01;
+        out.c[2][i] = (m2 - y) + 0.55;
+    }
+
+    out
+}
@@ -1,22 +1,27 @@
 use std::collections::HashSet;
 use std::io::Write;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
-use crossbeam_channel::{Receiver, Sender, bounded};
+use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
 
 use crate::chunk::{Chunk, ChunkComp, ResumeInf, get_resume, save_resume};
+use crate::crop::Crop;
+use crate::error::XavError;
 use crate::ffms::{
-    VidIdx, VidInf, calc_8bit_size, calc_10bit_size, calc_packed_size, conv_to_10bit,
+    VidIdx, VidInf, calc_8bit_size, calc_10bit_size, calc_packed_size, conv_to_8bit, conv_to_10bit,
     destroy_vid_src, extr_8bit, extr_10bit, pack_10bit, thr_vid_src, unpack_10bit,
 };
-use crate::progs::ProgsTrack;
+use crate::progs::{ProgsLog, ProgsTrack, watch_warnings};
+use crate::tools::ShowCmd;
 
 #[cfg(feature = "vship")]
-pub static TQ_SCORES: std::sync::OnceLock<std::sync::Mutex<Vec<f64>>> = std::sync::OnceLock::new();
+pub static TQ_SCORES: std::sync::OnceLock<std::sync::Mutex<Vec<(f64, usize)>>> =
+    std::sync::OnceLock::new();
 
 struct ChunkData {
     idx: usize,
@@ -27,12 +32,116 @@ struct ChunkData {
     height: u32,
 }
 
+struct BufPool {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    budget: Option<usize>,
+    in_use: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl BufPool {
+    fn new(count: usize, budget: Option<usize>) -> Self {
+        let (tx, rx) = bounded(count);
+        Self { tx, rx, budget, in_use: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    fn get(&self, len: usize, cap: usize) -> Vec<u8> {
+        let mut in_use = self.budget.map(|b| {
+            let mut used = self.in_use.lock().unwrap();
+            while *used > 0 && *used + cap > b {
+                used = self.freed.wait(used).unwrap();
+            }
+            used
+        });
+
+        let mut buf = self.rx.try_recv().unwrap_or_else(|_| Vec::with_capacity(cap));
+        buf.resize(len, 0);
+        if let Some(used) = in_use.as_mut() {
+            **used += buf.capacity();
+        }
+        buf
+    }
+
+    fn put(&self, buf: Vec<u8>) {
+        if self.budget.is_some() {
+            *self.in_use.lock().unwrap() -= buf.capacity();
+            self.freed.notify_one();
+        }
+        let _ = self.tx.try_send(buf);
+    }
+}
+
 struct EncConfig<'a> {
     inf: &'a VidInf,
     params: &'a str,
     crf: f32,
     output: &'a Path,
     grain_table: Option<&'a Path>,
+    meta: Vec<(&'static str, PathBuf)>,
+    pass: Option<(u8, &'a Path)>,
+}
+
+fn chunk_meta(inf: &VidInf, idx: usize) -> Vec<(&'static str, PathBuf)> {
+    inf.dyn_meta
+        .iter()
+        .map(|&(kind, ref dir)| (kind.svt_flag(), kind.chunk_file(dir, idx)))
+        .collect()
+}
+
+pub fn get_param<'a>(params: &'a str, key: &str) -> Option<&'a str> {
+    let mut parts = params.split_whitespace();
+    parts.by_ref().find(|p| *p == key)?;
+    parts.next()
+}
+
+pub fn profile(name: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    Some(match name {
+        "film" => &[
+            ("--tune", "0"),
+            ("--sharpness", "1"),
+            ("--enable-variance-boost", "1"),
+            ("--variance-boost-strength", "2"),
+        ],
+        "anime" => &[
+            ("--tune", "0"),
+            ("--sharpness", "2"),
+            ("--enable-variance-boost", "1"),
+            ("--variance-boost-strength", "1"),
+        ],
+        "grain" => &[
+            ("--tune", "0"),
+            ("--sharpness", "1"),
+            ("--enable-tf", "0"),
+            ("--enable-variance-boost", "1"),
+            ("--variance-boost-strength", "3"),
+        ],
+        "screen" => &[("--tune", "1"), ("--enable-tf", "0"), ("--enable-variance-boost", "0")],
+        _ => return None,
+    })
+}
+
+pub const fn rate_key(qp: bool) -> &'static str {
+    if qp { "--qp" } else { "--crf" }
+}
+
+fn rate_arg(qp: bool, crf: f64) -> (&'static str, String) {
+    (rate_key(qp), if qp { format!("{crf:.0}") } else { format!("{crf:.2}") })
+}
+
+pub fn set_rate(params: &str, qp: bool, crf: f64) -> String {
+    let (key, value) = rate_arg(qp, crf);
+    set_param(params, key, &value)
+}
+
+pub fn set_param(params: &str, key: &str, value: &str) -> String {
+    let mut parts: Vec<&str> = params.split_whitespace().collect();
+    match parts.iter().position(|p| *p == key) {
+        Some(i) if i + 1 < parts.len() => parts[i + 1] = value,
+        Some(_) => parts.push(value),
+        None => parts.extend([key, value]),
+    }
+    parts.join(" ")
 }
 
 fn make_enc_cmd(cfg: &EncConfig, quiet: bool, width: u32, height: u32) -> Command {
@@ -48,7 +157,7 @@ fn make_enc_cmd(cfg: &EncConfig, quiet: bool, width: u32, height: u32) -> Comman
         "-i",
         "stdin",
         "--input-depth",
-        "10",
+        if cfg.inf.out_8bit { "8" } else { "10" },
         "--width",
         &width_str,
         "--forced-max-frame-width",
@@ -78,8 +187,12 @@ fn make_enc_cmd(cfg: &EncConfig, quiet: bool, width: u32, height: u32) -> Comman
     }
 
     if cfg.crf >= 0.0 {
-        let crf_str = format!("{:.2}", cfg.crf);
-        cmd.arg("--crf").arg(crf_str);
+        let (key, value) = rate_arg(cfg.inf.rc_qp, f64::from(cfg.crf));
+        cmd.arg(key).arg(value);
+    }
+
+    if let Some((pass, stats)) = cfg.pass {
+        cmd.arg("--pass").arg(pass.to_string()).arg("--stats").arg(stats);
     }
 
     colorize(&mut cmd, cfg.inf);
@@ -88,6 +201,10 @@ fn make_enc_cmd(cfg: &EncConfig, quiet: bool, width: u32, height: u32) -> Comman
         cmd.arg("--fgs-table").arg(grain_path);
     }
 
+    for (flag, path) in &cfg.meta {
+        cmd.arg(flag).arg(path);
+    }
+
     if quiet {
         cmd.arg("--no-progress").arg("1");
     }
@@ -96,11 +213,26 @@ fn make_enc_cmd(cfg: &EncConfig, quiet: bool, width: u32, height: u32) -> Comman
         .arg("-b")
         .arg(cfg.output)
         .stdin(Stdio::piped())
-        .stderr(Stdio::piped());
+        .stderr(Stdio::piped())
+        .process_group(0);
 
     cmd
 }
 
+pub fn enc_cmd_line(inf: &VidInf, params: &str, crop: Crop, grain_table: Option<&Path>) -> String {
+    let cfg = EncConfig {
+        inf,
+        params,
+        crf: -1.0,
+        output: Path::new("0000.ivf"),
+        grain_table,
+        meta: chunk_meta(inf, 0),
+        pass: None,
+    };
+    let (width, height) = crop.dims(inf.width, inf.height);
+    crate::tools::cmd_line(&make_enc_cmd(&cfg, false, width, height))
+}
+
 fn colorize(cmd: &mut Command, inf: &VidInf) {
     if let Some(cp) = inf.color_primaries {
         cmd.args(["--color-primaries", &cp.to_string()]);
@@ -125,21 +257,27 @@ fn colorize(cmd: &mut Command, inf: &VidInf) {
     }
 }
 
+fn pending(chunks: &Receiver<Chunk>) -> impl Iterator<Item = Chunk> + '_ {
+    chunks.iter().take_while(|_| !crate::STOP.load(Ordering::Relaxed))
+}
+
 fn dec_10bit(
-    chunks: &[Chunk],
+    chunks: &Receiver<Chunk>,
+    max_len: usize,
     source: *mut std::ffi::c_void,
     inf: &VidInf,
     tx: &Sender<ChunkData>,
-    crop: (u32, u32),
+    crop: Crop,
+    pool: &BufPool,
 ) {
-    if crop == (0, 0) {
+    if crop.is_none() {
         let frame_size = calc_10bit_size(inf);
         let packed_size = calc_packed_size(inf);
         let mut frame_buf = vec![0u8; frame_size];
 
-        for chunk in chunks {
+        for chunk in pending(chunks) {
             let chunk_len = chunk.end - chunk.start;
-            let mut frames_data = vec![0u8; chunk_len * packed_size];
+            let mut frames_data = pool.get(chunk_len * packed_size, max_len * packed_size);
             let mut valid = 0;
 
             for (i, idx) in (chunk.start..chunk.end).enumerate() {
@@ -165,12 +303,12 @@ fn dec_10bit(
                     height: inf.height,
                 })
                 .ok();
+            } else {
+                pool.put(frames_data);
             }
         }
     } else {
-        let (crop_v, crop_h) = crop;
-        let new_width = inf.width - crop_h * 2;
-        let new_height = inf.height - crop_v * 2;
+        let (new_width, new_height) = crop.dims(inf.width, inf.height);
 
         let orig_frame_size = calc_10bit_size(inf);
         let new_y_size = (new_width * new_height * 2) as usize;
@@ -180,21 +318,21 @@ fn dec_10bit(
 
         let y_stride = (inf.width * 2) as usize;
         let uv_stride = (inf.width / 2 * 2) as usize;
-        let y_start = ((crop_v * inf.width + crop_h) as usize) * 2;
+        let y_start = ((crop.top * inf.width + crop.left) as usize) * 2;
         let y_plane_size = (inf.width * inf.height) as usize * 2;
         let uv_plane_size = (inf.width / 2 * inf.height / 2) as usize * 2;
-        let u_start = y_plane_size + ((crop_v / 2 * inf.width / 2 + crop_h / 2) as usize * 2);
-        let v_start =
-            y_plane_size + uv_plane_size + ((crop_v / 2 * inf.width / 2 + crop_h / 2) as usize * 2);
+        let uv_off = (crop.top / 2 * inf.width / 2 + crop.left / 2) as usize * 2;
+        let u_start = y_plane_size + uv_off;
+        let v_start = y_plane_size + uv_plane_size + uv_off;
         let y_len = (new_width * 2) as usize;
         let uv_len = (new_width / 2 * 2) as usize;
 
         let mut frame_buf = vec![0u8; orig_frame_size];
         let mut cropped_buf = vec![0u8; new_frame_size];
 
-        for chunk in chunks {
+        for chunk in pending(chunks) {
             let chunk_len = chunk.end - chunk.start;
-            let mut frames_data = vec![0u8; chunk_len * new_packed_size];
+            let mut frames_data = pool.get(chunk_len * new_packed_size, max_len * new_packed_size);
             let mut valid = 0;
 
             for (i, idx) in (chunk.start..chunk.end).enumerate() {
@@ -241,24 +379,28 @@ fn dec_10bit(
                     height: new_height,
                 })
                 .ok();
+            } else {
+                pool.put(frames_data);
             }
         }
     }
 }
 
 fn dec_8bit(
-    chunks: &[Chunk],
+    chunks: &Receiver<Chunk>,
+    max_len: usize,
     source: *mut std::ffi::c_void,
     inf: &VidInf,
     tx: &Sender<ChunkData>,
-    crop: (u32, u32),
+    crop: Crop,
+    pool: &BufPool,
 ) {
-    if crop == (0, 0) {
+    if crop.is_none() {
         let frame_size = calc_8bit_size(inf);
 
-        for chunk in chunks {
+        for chunk in pending(chunks) {
             let chunk_len = chunk.end - chunk.start;
-            let mut frames_data = vec![0u8; chunk_len * frame_size];
+            let mut frames_data = pool.get(chunk_len * frame_size, max_len * frame_size);
             let mut valid = 0;
 
             for (i, idx) in (chunk.start..chunk.end).enumerate() {
@@ -281,12 +423,12 @@ fn dec_8bit(
                     height: inf.height,
                 })
                 .ok();
+            } else {
+                pool.put(frames_data);
             }
         }
     } else {
-        let (crop_v, crop_h) = crop;
-        let new_width = inf.width - crop_h * 2;
-        let new_height = inf.height - crop_v * 2;
+        let (new_width, new_height) = crop.dims(inf.width, inf.height);
 
         let orig_frame_size = calc_8bit_size(inf);
         let new_y_size = (new_width * new_height) as usize;
@@ -295,20 +437,20 @@ fn dec_8bit(
 
         let y_stride = inf.width as usize;
         let uv_stride = (inf.width / 2) as usize;
-        let y_start = (crop_v * inf.width + crop_h) as usize;
+        let y_start = (crop.top * inf.width + crop.left) as usize;
         let y_plane_size = (inf.width * inf.height) as usize;
         let uv_plane_size = (inf.width / 2 * inf.height / 2) as usize;
-        let u_start = y_plane_size + ((crop_v / 2 * inf.width / 2 + crop_h / 2) as usize);
-        let v_start =
-            y_plane_size + uv_plane_size + ((crop_v / 2 * inf.width / 2 + crop_h / 2) as usize);
+        let uv_off = (crop.top / 2 * inf.width / 2 + crop.left / 2) as usize;
+        let u_start = y_plane_size + uv_off;
+        let v_start = y_plane_size + uv_plane_size + uv_off;
         let y_len = new_width as usize;
         let uv_len = (new_width / 2) as usize;
 
         let mut frame_buf = vec![0u8; orig_frame_size];
 
-        for chunk in chunks {
+        for chunk in pending(chunks) {
             let chunk_len = chunk.end - chunk.start;
-            let mut frames_data = vec![0u8; chunk_len * new_frame_size];
+            let mut frames_data = pool.get(chunk_len * new_frame_size, max_len * new_frame_size);
             let mut valid = 0;
 
             for (i, idx) in (chunk.start..chunk.end).enumerate() {
@@ -351,29 +493,69 @@ fn dec_8bit(
                     height: new_height,
                 })
                 .ok();
+            } else {
+                pool.put(frames_data);
             }
         }
     }
 }
 
-fn decode_chunks(
+fn sched_order(chunks: &[Chunk], sequential: bool) -> Vec<Chunk> {
+    let mut order = chunks.to_vec();
+    if !sequential {
+        order.sort_by_key(|c| std::cmp::Reverse(c.end - c.start));
+    }
+    order
+}
+
+fn chunk_queue(
     chunks: &[Chunk],
-    idx: &Arc<VidIdx>,
+    feed: Option<Receiver<Chunk>>,
+    sequential: bool,
+    skip_indices: HashSet<usize>,
+) -> Receiver<Chunk> {
+    let (tx, rx) = unbounded();
+    if let Some(feed) = feed {
+        thread::spawn(move || {
+            for chunk in feed.iter().filter(|c| !skip_indices.contains(&c.idx)) {
+                if tx.send(chunk).is_err() {
+                    break;
+                }
+            }
+        });
+    } else {
+        for chunk in sched_order(chunks, sequential) {
+            if !skip_indices.contains(&chunk.idx) {
+                tx.send(chunk).ok();
+            }
+        }
+    }
+    rx
+}
+
+fn max_chunk_len(chunks: &[Chunk], inf: &VidInf, args: &crate::Args) -> usize {
+    chunks.iter().map(|c| c.end - c.start).max().unwrap_or_else(|| {
+        let max_len = crate::chunk::scene_limits(inf.fps_num, inf.fps_den, args.scene_len)
+            .map_or(300, |l| l.1);
+        args.split.map_or(max_len, |s| s.max(max_len))
+    })
+}
+
+fn decode_chunks(
+    chunks: &Receiver<Chunk>,
+    max_len: usize,
+    (idx, threads): (&Arc<VidIdx>, usize),
     inf: &VidInf,
     tx: &Sender<ChunkData>,
-    skip_indices: &HashSet<usize>,
-    crop: (u32, u32),
+    crop: Crop,
+    pool: &BufPool,
 ) {
-    let threads =
-        std::thread::available_parallelism().map_or(8, |n| n.get().try_into().unwrap_or(8));
-    let Ok(source) = thr_vid_src(idx, threads) else { return };
-    let filtered: Vec<Chunk> =
-        chunks.iter().filter(|c| !skip_indices.contains(&c.idx)).cloned().collect();
-
-    if inf.is_10bit {
-        dec_10bit(&filtered, source, inf, tx, crop);
+    let Ok(source) = thr_vid_src(idx, threads.try_into().unwrap_or(8)) else { return };
+
+    if inf.is_10bit() {
+        dec_10bit(chunks, max_len, source, inf, tx, crop, pool);
     } else {
-        dec_8bit(&filtered, source, inf, tx, crop);
+        dec_8bit(chunks, max_len, source, inf, tx, crop, pool);
     }
 
     destroy_vid_src(source);
@@ -400,12 +582,18 @@ fn write_frames(
 
     let mut written = 0;
 
-    if let Some(buf) = conversion_buf {
-        if inf.is_10bit {
+    if let Some(buf) = conversion_buf.as_mut().filter(|_| !inf.out_8bit || inf.is_10bit()) {
+        if inf.is_10bit() {
             for i in 0..frame_count {
                 let frame = get_frame(frames, i, frame_size);
                 unpack_10bit(frame, buf);
-                if stdin.write_all(buf).is_err() {
+                let out = if inf.out_8bit {
+                    conv_to_8bit(buf);
+                    &buf[..buf.len() / 2]
+                } else {
+                    &buf[..]
+                };
+                if stdin.write_all(out).is_err() {
                     break;
                 }
                 written += 1;
@@ -439,6 +627,21 @@ struct ProcConfig<'a> {
     quiet: bool,
     work_dir: &'a Path,
     grain_table: Option<&'a Path>,
+    two_pass: bool,
+}
+
+fn enc_failed(
+    idx: usize,
+    status: std::process::ExitStatus,
+    retried: &mut bool,
+) -> Result<(), XavError> {
+    let msg = format!("Chunk {idx:04} failed to encode ({status})");
+    if *retried {
+        return Err(XavError::Encode(msg));
+    }
+    *retried = true;
+    eprintln!("{msg}, retrying");
+    Ok(())
 }
 
 fn proc_chunk(
@@ -446,38 +649,62 @@ fn proc_chunk(
     config: &ProcConfig,
     prog: Option<&ProgsTrack>,
     conversion_buf: &mut Option<Vec<u8>>,
-) -> (usize, Option<ChunkComp>) {
+) -> Result<(usize, Option<ChunkComp>), XavError> {
     let output = config.work_dir.join("encode").join(format!("{:04}.ivf", data.idx));
-    let enc_cfg = EncConfig {
-        inf: config.inf,
-        params: config.params,
-        crf: -1.0,
-        output: &output,
-        grain_table: config.grain_table,
-    };
-    let mut cmd = make_enc_cmd(&enc_cfg, config.quiet, data.width, data.height);
-    let mut child = cmd.spawn().unwrap_or_else(|_| std::process::exit(1));
-
-    if !config.quiet
-        && let Some(stderr) = child.stderr.take()
-        && let Some(p) = prog
-    {
-        p.watch_enc(stderr, data.idx, true, None);
-    }
+    let stats = config.work_dir.join("split").join(format!("{:04}.stat", data.idx));
+    let passes: &[Option<(u8, &Path)>] =
+        if config.two_pass { &[Some((1, &stats)), Some((2, &stats))] } else { &[None] };
 
     let frame_count = data.frame_count;
-    let written = write_frames(
-        &mut child,
-        &data.frames,
-        data.frame_size,
-        data.frame_count,
-        config.inf,
-        conversion_buf,
-    );
+    let mut written = 0;
+    let mut retried = false;
+
+    crate::progs::chunk_start(data.idx, None);
+
+    'retry: loop {
+        for &pass in passes {
+            let enc_cfg = EncConfig {
+                inf: config.inf,
+                params: config.params,
+                crf: -1.0,
+                output: &output,
+                grain_table: config.grain_table,
+                meta: chunk_meta(config.inf, data.idx),
+                pass,
+            };
+            let mut cmd = make_enc_cmd(&enc_cfg, config.quiet, data.width, data.height);
+            let mut child = spawn_enc(&mut cmd)?;
+
+            let final_pass = pass.is_none_or(|(p, _)| p == 2);
+            if !config.quiet
+                && let Some(stderr) = child.stderr.take()
+                && let Some(p) = prog
+            {
+                p.watch_enc(stderr, data.idx, final_pass, None);
+            } else if let Some(stderr) = child.stderr.take() {
+                watch_warnings(stderr, data.idx);
+            }
 
-    let status = child.wait().unwrap();
-    if !status.success() {
-        std::process::exit(1);
+            written = write_frames(
+                &mut child,
+                &data.frames,
+                data.frame_size,
+                data.frame_count,
+                config.inf,
+                conversion_buf,
+            );
+
+            let status = child.wait().unwrap();
+            if !status.success() {
+                enc_failed(data.idx, status, &mut retried)?;
+                continue 'retry;
+            }
+        }
+        break;
+    }
+
+    if config.two_pass {
+        let _ = std::fs::remove_file(&stats);
     }
 
     let completion = std::fs::metadata(&output).ok().map(|metadata| ChunkComp {
@@ -486,12 +713,25 @@ fn proc_chunk(
         size: metadata.len(),
     });
 
-    (written, completion)
+    Ok((written, completion))
+}
+
+fn spawn_enc(cmd: &mut Command) -> Result<std::process::Child, XavError> {
+    cmd.show().spawn().map_err(|e| XavError::Tool(format!("Failed to run SvtAv1EncApp: {e}")))
+}
+
+fn abort_on_err<T>(res: Result<T, XavError>) -> Result<T, XavError> {
+    if res.is_err() {
+        crate::STOP.store(true, Ordering::SeqCst);
+    }
+    res
 }
 
 struct WorkerCtx<'a> {
     quiet: bool,
     grain_table: Option<&'a Path>,
+    pool: &'a BufPool,
+    two_pass: bool,
 }
 
 fn run_worker(
@@ -502,16 +742,22 @@ fn run_worker(
     stats: Option<&Arc<WorkerStats>>,
     prog: Option<&Arc<ProgsTrack>>,
     work_dir: &Path,
-) {
+) -> Result<(), XavError> {
     let mut current_inf = inf.clone();
-    let mut conversion_buf = Some(vec![0u8; calc_10bit_size(&current_inf)]);
+    let mut conversion_buf = Some(Vec::new());
     let mut first_chunk = true;
 
     while let Ok(data) = rx.recv() {
+        if crate::STOP.load(Ordering::Relaxed) {
+            ctx.pool.put(data.frames);
+            continue;
+        }
         if first_chunk || (data.width != current_inf.width || data.height != current_inf.height) {
             current_inf.width = data.width;
             current_inf.height = data.height;
-            conversion_buf = Some(vec![0u8; calc_10bit_size(&current_inf)]);
+            if let Some(buf) = conversion_buf.as_mut() {
+                buf.resize(calc_10bit_size(&current_inf), 0);
+            }
             first_chunk = false;
         }
 
@@ -521,9 +767,11 @@ fn run_worker(
             quiet: ctx.quiet,
             work_dir,
             grain_table: ctx.grain_table,
+            two_pass: ctx.two_pass,
         };
-        let (written, completion) =
-            proc_chunk(&data, &config, prog.map(AsRef::as_ref), &mut conversion_buf);
+        let done = proc_chunk(&data, &config, prog.map(AsRef::as_ref), &mut conversion_buf);
+        ctx.pool.put(data.frames);
+        let (written, completion) = abort_on_err(done)?;
 
         if let Some(s) = stats {
             s.completed.fetch_add(1, Ordering::Relaxed);
@@ -534,11 +782,228 @@ fn run_worker(
             }
         }
     }
+    Ok(())
+}
+
+const STREAM_RING: usize = 4;
+
+fn crop_frame(src: &[u8], dst: &mut [u8], inf: &VidInf, crop: Crop, bps: usize) {
+    let (new_w, new_h) = crop.dims(inf.width, inf.height);
+    let (w, h) = (inf.width as usize, inf.height as usize);
+    let luma = (0, w, crop.top as usize, crop.left as usize, new_w as usize, new_h as usize);
+    let chroma = |base| {
+        let (top, left) = ((crop.top / 2) as usize, (crop.left / 2) as usize);
+        (base, w / 2, top, left, (new_w / 2) as usize, (new_h / 2) as usize)
+    };
+
+    let mut pos = 0;
+    for (base, stride, top, left, len, rows) in [luma, chroma(w * h), chroma(w * h + w * h / 4)] {
+        for row in 0..rows {
+            let src_pos = (base + (top + row) * stride + left) * bps;
+            dst[pos..pos + len * bps].copy_from_slice(&src[src_pos..src_pos + len * bps]);
+            pos += len * bps;
+        }
+    }
+}
+
+fn decode_frame(
+    source: *mut std::ffi::c_void,
+    idx: usize,
+    inf: &VidInf,
+    crop: Crop,
+    scratch: &mut (Vec<u8>, Vec<u8>),
+    out: &mut [u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (frame, cropped) = scratch;
+    match (inf.is_10bit(), crop.is_none()) {
+        (false, true) if inf.out_8bit => extr_8bit(source, idx, out)?,
+        (false, false) if inf.out_8bit => {
+            extr_8bit(source, idx, frame)?;
+            crop_frame(frame, out, inf, crop, 1);
+        }
+        (true, true) if inf.out_8bit => {
+            extr_10bit(source, idx, frame)?;
+            conv_to_8bit(frame);
+            out.copy_from_slice(&frame[..out.len()]);
+        }
+        (true, false) if inf.out_8bit => {
+            extr_10bit(source, idx, frame)?;
+            conv_to_8bit(frame);
+            crop_frame(&frame[..frame.len() / 2], out, inf, crop, 1);
+        }
+        (true, true) => extr_10bit(source, idx, out)?,
+        (true, false) => {
+            extr_10bit(source, idx, frame)?;
+            crop_frame(frame, out, inf, crop, 2);
+        }
+        (false, true) => {
+            extr_8bit(source, idx, frame)?;
+            conv_to_10bit(frame, out);
+        }
+        (false, false) => {
+            extr_8bit(source, idx, frame)?;
+            crop_frame(frame, cropped, inf, crop, 1);
+            conv_to_10bit(cropped, out);
+        }
+    }
+    Ok(())
+}
+
+fn stream_frames(
+    child: &mut std::process::Child,
+    source: *mut std::ffi::c_void,
+    chunk: &Chunk,
+    inf: &VidInf,
+    crop: Crop,
+    scratch: &mut (Vec<u8>, Vec<u8>),
+) -> usize {
+    let Some(mut stdin) = child.stdin.take() else {
+        return 0;
+    };
+
+    let (width, height) = crop.dims(inf.width, inf.height);
+    let frame_size =
+        if inf.out_8bit { width * height * 3 / 2 } else { width * height * 3 } as usize;
+    let (full_tx, full_rx) = bounded::<Vec<u8>>(STREAM_RING);
+    let (free_tx, free_rx) = bounded::<Vec<u8>>(STREAM_RING);
+    for _ in 0..STREAM_RING {
+        free_tx.send(vec![0u8; frame_size]).ok();
+    }
+
+    thread::scope(|s| {
+        let writer = s.spawn(move || {
+            let mut written = 0;
+            for frame in full_rx {
+                if stdin.write_all(&frame).is_err() {
+                    break;
+                }
+                written += 1;
+                if free_tx.send(frame).is_err() {
+                    break;
+                }
+            }
+            written
+        });
+
+        let mut spare = None;
+        for idx in chunk.start..chunk.end {
+            let Some(mut frame) = spare.take().or_else(|| free_rx.recv().ok()) else { break };
+            if decode_frame(source, idx, inf, crop, scratch, &mut frame).is_err() {
+                spare = Some(frame);
+                continue;
+            }
+            if full_tx.send(frame).is_err() {
+                break;
+            }
+        }
+        drop(full_tx);
+
+        writer.join().unwrap()
+    })
+}
+
+fn stream_chunk(
+    source: *mut std::ffi::c_void,
+    chunk: &Chunk,
+    config: &ProcConfig,
+    crop: Crop,
+    prog: Option<&ProgsTrack>,
+    scratch: &mut (Vec<u8>, Vec<u8>),
+) -> Result<(usize, Option<ChunkComp>), XavError> {
+    let output = config.work_dir.join("encode").join(format!("{:04}.ivf", chunk.idx));
+    let stats = config.work_dir.join("split").join(format!("{:04}.stat", chunk.idx));
+    let passes: &[Option<(u8, &Path)>] =
+        if config.two_pass { &[Some((1, &stats)), Some((2, &stats))] } else { &[None] };
+    let (width, height) = crop.dims(config.inf.width, config.inf.height);
+    let mut written = 0;
+    let mut retried = false;
+
+    crate::progs::chunk_start(chunk.idx, None);
+
+    'retry: loop {
+        for &pass in passes {
+            let enc_cfg = EncConfig {
+                inf: config.inf,
+                params: config.params,
+                crf: -1.0,
+                output: &output,
+                grain_table: config.grain_table,
+                meta: chunk_meta(config.inf, chunk.idx),
+                pass,
+            };
+            let mut child = spawn_enc(&mut make_enc_cmd(&enc_cfg, config.quiet, width, height))?;
+
+            let final_pass = pass.is_none_or(|(p, _)| p == 2);
+            if !config.quiet
+                && let Some(stderr) = child.stderr.take()
+                && let Some(p) = prog
+            {
+                p.watch_enc(stderr, chunk.idx, final_pass, None);
+            } else if let Some(stderr) = child.stderr.take() {
+                watch_warnings(stderr, chunk.idx);
+            }
+
+            written = stream_frames(&mut child, source, chunk, config.inf, crop, scratch);
+
+            let status = child.wait().unwrap();
+            if !status.success() {
+                enc_failed(chunk.idx, status, &mut retried)?;
+                continue 'retry;
+            }
+        }
+        break;
+    }
+
+    if config.two_pass {
+        let _ = std::fs::remove_file(&stats);
+    }
+
+    let completion = std::fs::metadata(&output).ok().map(|metadata| ChunkComp {
+        idx: chunk.idx,
+        frames: written,
+        size: metadata.len(),
+    });
+
+    Ok((written, completion))
+}
+
+fn run_stream_worker(
+    rx: &Receiver<Chunk>,
+    source: *mut std::ffi::c_void,
+    config: &ProcConfig,
+    crop: Crop,
+    stats: Option<&Arc<WorkerStats>>,
+    prog: Option<&Arc<ProgsTrack>>,
+) -> Result<(), XavError> {
+    let (width, height) = crop.dims(config.inf.width, config.inf.height);
+    let mut scratch =
+        (vec![0u8; calc_10bit_size(config.inf)], vec![0u8; (width * height * 3 / 2) as usize]);
+
+    for chunk in pending(rx) {
+        let (written, completion) = abort_on_err(stream_chunk(
+            source,
+            &chunk,
+            config,
+            crop,
+            prog.map(AsRef::as_ref),
+            &mut scratch,
+        ))?;
+
+        if let Some(s) = stats {
+            s.completed.fetch_add(1, Ordering::Relaxed);
+            s.frames_done.fetch_add(written, Ordering::Relaxed);
+
+            if let Some(comp) = completion {
+                s.add_completion(comp, config.work_dir);
+            }
+        }
+    }
+    Ok(())
 }
 
 struct WorkerStats {
     completed: Arc<AtomicUsize>,
-    frames_done: AtomicUsize,
+    frames_done: Arc<AtomicUsize>,
     completions: Arc<std::sync::Mutex<ResumeInf>>,
 }
 
@@ -546,12 +1011,13 @@ impl WorkerStats {
     fn new(initial_completed: usize, init_frames: usize, initial_data: ResumeInf) -> Self {
         Self {
             completed: Arc::new(AtomicUsize::new(initial_completed)),
-            frames_done: AtomicUsize::new(init_frames),
+            frames_done: Arc::new(AtomicUsize::new(init_frames)),
             completions: Arc::new(std::sync::Mutex::new(initial_data)),
         }
     }
 
     fn add_completion(&self, completion: ChunkComp, work_dir: &Path) {
+        crate::progs::chunk_done(&completion);
         let mut data = self.completions.lock().unwrap();
         data.chnks_done.push(completion);
         let _ = save_resume(&data, work_dir);
@@ -559,14 +1025,174 @@ impl WorkerStats {
     }
 }
 
+const SIZE_SAMPLES: usize = 12;
+const SIZE_ROUNDS: usize = 4;
+const SIZE_TOLERANCE: f64 = 0.03;
+
+fn sample_size(
+    samples: &[Chunk],
+    crf: f32,
+    config: &ProcConfig,
+    idx: &Arc<VidIdx>,
+    args: &crate::Args,
+) -> Result<u64, XavError> {
+    let crop = args.crop.unwrap_or_default();
+    let threads = (args.threads / args.worker).clamp(1, 8) as i32;
+    let next = AtomicUsize::new(0);
+    let total = AtomicU64::new(0);
+
+    let failed = thread::scope(|s| {
+        let workers: Vec<_> = (0..args.worker.min(samples.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let Ok(source) = thr_vid_src(idx, threads) else { return Ok(()) };
+                    let res = probe_sizes(samples, (crf, crop), config, (&next, &total), source);
+                    let res = abort_on_err(res);
+                    destroy_vid_src(source);
+                    res
+                })
+            })
+            .collect();
+        workers.into_iter().filter_map(|h| h.join().unwrap().err()).next()
+    });
+
+    failed.map_or(Ok(total.into_inner()), Err)
+}
+
+fn probe_sizes(
+    samples: &[Chunk],
+    (crf, crop): (f32, Crop),
+    config: &ProcConfig,
+    (next, total): (&AtomicUsize, &AtomicU64),
+    source: *mut std::ffi::c_void,
+) -> Result<(), XavError> {
+    let (width, height) = crop.dims(config.inf.width, config.inf.height);
+    let mut scratch =
+        (vec![0u8; calc_10bit_size(config.inf)], vec![0u8; (width * height * 3 / 2) as usize]);
+
+    while let Some(chunk) = samples.get(next.fetch_add(1, Ordering::Relaxed)) {
+        if crate::STOP.load(Ordering::Relaxed) {
+            break;
+        }
+        let output = config.work_dir.join("split").join(format!("size_{:04}.ivf", chunk.idx));
+        let enc_cfg = EncConfig {
+            inf: config.inf,
+            params: config.params,
+            crf,
+            output: &output,
+            grain_table: config.grain_table,
+            meta: chunk_meta(config.inf, chunk.idx),
+            pass: None,
+        };
+        let mut child = spawn_enc(&mut make_enc_cmd(&enc_cfg, true, width, height))?;
+        stream_frames(&mut child, source, chunk, config.inf, crop, &mut scratch);
+
+        let status = child.wait().unwrap();
+        if !status.success() {
+            return Err(XavError::Encode(format!(
+                "Size probe {:04} failed to encode ({status})",
+                chunk.idx
+            )));
+        }
+        let size = std::fs::metadata(&output).map_or(0, |m| m.len());
+        total.fetch_add(size, Ordering::Relaxed);
+        let _ = std::fs::remove_file(&output);
+    }
+    Ok(())
+}
+
+pub fn size_search(
+    chunks: &[Chunk],
+    inf: &VidInf,
+    args: &crate::Args,
+    idx: &Arc<VidIdx>,
+    work_dir: &Path,
+    grain_table: Option<&Path>,
+) -> Result<(f32, u64), XavError> {
+    let budget = args.target_size.unwrap_or_default() * 1_000_000.0;
+    let step = chunks.len().div_ceil(SIZE_SAMPLES).max(1);
+    let samples: Vec<Chunk> = chunks.iter().step_by(step).cloned().collect();
+    let sampled: usize = samples.iter().map(|c| c.end - c.start).sum();
+    let scale =
+        chunks.iter().map(|c| c.end - c.start).sum::<usize>() as f64 / sampled.max(1) as f64;
+
+    let config = ProcConfig {
+        inf,
+        params: &args.params,
+        quiet: true,
+        work_dir,
+        grain_table,
+        two_pass: false,
+    };
+    let predict = |crf: f32| {
+        let size = (sample_size(&samples, crf, &config, idx, args)? as f64 * scale).max(1.0);
+        if !args.quiet {
+            eprintln!("Size probe: CRF {crf:.2} -> {:.1} MB", size / 1_000_000.0);
+        }
+        Ok::<_, XavError>((crf, size))
+    };
+    let miss = |size: f64| (size.ln() - budget.ln()).abs();
+
+    let mut probes = vec![predict(25.0)?, predict(45.0)?];
+    for _ in 0..SIZE_ROUNDS {
+        probes.sort_by(|a, b| miss(a.1).total_cmp(&miss(b.1)));
+        let ((c1, s1), (c2, s2)) = (probes[0], probes[1]);
+        if (s1 / budget - 1.0).abs() < SIZE_TOLERANCE {
+            break;
+        }
+
+        let slope = (s2.ln() - s1.ln()) / f64::from(c2 - c1);
+        if slope >= 0.0 {
+            break;
+        }
+        let crf = f64::from(c1) + (budget.ln() - s1.ln()) / slope;
+        let crf = ((crf.clamp(1.0, 70.0) * 4.0).round() / 4.0) as f32;
+        if probes.iter().any(|p| p.0 == crf) {
+            break;
+        }
+        probes.push(predict(crf)?);
+    }
+
+    let (crf, size) = probes.into_iter().min_by(|a, b| miss(a.1).total_cmp(&miss(b.1))).unwrap();
+    Ok((crf, size as u64))
+}
+
+fn pin_worker(worker: usize, workers: usize) {
+    let size = size_of::<libc::cpu_set_t>();
+    unsafe {
+        let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, size, &raw mut allowed) != 0 {
+            eprintln!("Warning: cannot read CPU affinity: {}", std::io::Error::last_os_error());
+            return;
+        }
+        let cpus: Vec<usize> =
+            (0..libc::CPU_SETSIZE as usize).filter(|&c| libc::CPU_ISSET(c, &allowed)).collect();
+        if cpus.is_empty() {
+            return;
+        }
+
+        let per = (cpus.len() / workers.max(1)).max(1);
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for i in worker * per..(worker + 1) * per {
+            libc::CPU_SET(cpus[i % cpus.len()], &mut set);
+        }
+        if libc::sched_setaffinity(0, size, &raw const set) != 0 {
+            eprintln!("Warning: cannot pin worker {worker}: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
 pub fn encode_all(
     chunks: &[Chunk],
+    feed: Option<Receiver<Chunk>>,
     inf: &VidInf,
     args: &crate::Args,
     idx: &Arc<VidIdx>,
     work_dir: &Path,
     grain_table: Option<&PathBuf>,
-) {
+) -> Result<(), XavError> {
+    crate::ENCODING.store(true, Ordering::Relaxed);
+
     let resume_data = if args.resume {
         get_resume(work_dir).unwrap_or(ResumeInf { chnks_done: Vec::new() })
     } else {
@@ -577,8 +1203,7 @@ pub fn encode_all(
     {
         let is_tq = args.target_quality.is_some() && args.qp_range.is_some();
         if is_tq {
-            encode_tq(chunks, inf, args, idx, work_dir, grain_table);
-            return;
+            return encode_tq(chunks, feed, inf, args, idx, work_dir, grain_table);
         }
     }
 
@@ -586,12 +1211,14 @@ pub fn encode_all(
     let completed_count = skip_indices.len();
     let completed_frames: usize = resume_data.chnks_done.iter().map(|c| c.frames).sum();
 
-    let stats = if args.quiet {
+    let stats = if args.quiet && args.log.is_none() && args.progress_socket.is_none() {
         None
     } else {
         Some(Arc::new(WorkerStats::new(completed_count, completed_frames, resume_data)))
     };
 
+    let log = start_log(args, chunks, inf, completed_frames, stats.as_ref());
+
     let prog = if args.quiet {
         None
     } else {
@@ -605,46 +1232,122 @@ pub fn encode_all(
         )))
     };
 
-    let buffer_size = 0;
-    let (tx, rx) = bounded::<ChunkData>(buffer_size);
-    let rx = Arc::new(rx);
-
-    let crop = args.crop.unwrap_or((0, 0));
+    let max_len = max_chunk_len(chunks, inf, args);
+    let queue = chunk_queue(chunks, feed, args.sequential, skip_indices);
+    let crop = args.crop.unwrap_or_default();
+    let quiet = args.quiet;
+    let two_pass = args.two_pass;
+    let pin = args.pin.then_some(args.worker);
+    let mut workers = Vec::new();
 
-    let decoder = {
-        let chunks = chunks.to_vec();
-        let idx = Arc::clone(idx);
-        let inf = inf.clone();
-        thread::spawn(move || decode_chunks(&chunks, &idx, &inf, &tx, &skip_indices, crop))
-    };
+    let decoder = if args.stream {
+        let rx = Arc::new(queue);
+        let threads = (args.threads / args.worker).clamp(1, 8) as i32;
+
+        for worker_idx in 0..args.worker {
+            let rx = Arc::clone(&rx);
+            let idx = Arc::clone(idx);
+            let inf = inf.clone();
+            let params = args.params.clone();
+            let stats = stats.clone();
+            let prog = prog.clone();
+            let grain = grain_table.cloned();
+            let work_dir = work_dir.to_path_buf();
+
+            workers.push(thread::spawn(move || {
+                if let Some(n) = pin {
+                    pin_worker(worker_idx, n);
+                }
+                let Ok(source) = thr_vid_src(&idx, threads) else { return Ok(()) };
+                let config = ProcConfig {
+                    inf: &inf,
+                    params: &params,
+                    quiet,
+                    work_dir: &work_dir,
+                    grain_table: grain.as_deref(),
+                    two_pass,
+                };
+                let res =
+                    run_stream_worker(&rx, source, &config, crop, stats.as_ref(), prog.as_ref());
+                destroy_vid_src(source);
+                res
+            }));
+        }
 
-    let mut workers = Vec::new();
-    let quiet = args.quiet;
-    for _ in 0..args.worker {
-        let rx = Arc::clone(&rx);
-        let inf = inf.clone();
-        let params = args.params.clone();
-        let stats = stats.clone();
-        let prog = prog.clone();
-        let grain = grain_table.cloned();
-        let work_dir = work_dir.to_path_buf();
+        None
+    } else {
+        let (tx, rx) = bounded::<ChunkData>(0);
+        let rx = Arc::new(rx);
+        let pool = Arc::new(BufPool::new(args.pool, args.mem));
+
+        let decoder = {
+            let idx = Arc::clone(idx);
+            let inf = inf.clone();
+            let pool = Arc::clone(&pool);
+            let threads = args.threads;
+            thread::spawn(move || {
+                decode_chunks(&queue, max_len, (&idx, threads), &inf, &tx, crop, &pool);
+            })
+        };
 
-        let handle = thread::spawn(move || {
-            let ctx = WorkerCtx { quiet, grain_table: grain.as_deref() };
-            run_worker(&rx, &inf, &params, &ctx, stats.as_ref(), prog.as_ref(), &work_dir);
-        });
-        workers.push(handle);
-    }
+        for worker_idx in 0..args.worker {
+            let rx = Arc::clone(&rx);
+            let inf = inf.clone();
+            let params = args.params.clone();
+            let stats = stats.clone();
+            let prog = prog.clone();
+            let grain = grain_table.cloned();
+            let work_dir = work_dir.to_path_buf();
+            let pool = Arc::clone(&pool);
+
+            workers.push(thread::spawn(move || {
+                if let Some(n) = pin {
+                    pin_worker(worker_idx, n);
+                }
+                let ctx = WorkerCtx { quiet, grain_table: grain.as_deref(), pool: &pool, two_pass };
+                run_worker(&rx, &inf, &params, &ctx, stats.as_ref(), prog.as_ref(), &work_dir)
+            }));
+        }
 
-    decoder.join().unwrap();
+        Some(decoder)
+    };
 
-    for handle in workers {
-        handle.join().unwrap();
+    if let Some(d) = decoder {
+        d.join().unwrap();
     }
 
+    let failed = workers.into_iter().filter_map(|h| h.join().unwrap().err()).next();
+
     if let Some(ref p) = prog {
         p.final_update();
     }
+    if let Some(l) = log {
+        l.finish();
+    }
+    failed.map_or(Ok(()), Err)
+}
+
+fn start_log(
+    args: &crate::Args,
+    chunks: &[Chunk],
+    inf: &VidInf,
+    completed_frames: usize,
+    stats: Option<&Arc<WorkerStats>>,
+) -> Option<ProgsLog> {
+    let s = stats?;
+    if args.log.is_none() && args.progress_socket.is_none() {
+        return None;
+    }
+    ProgsLog::start(
+        args.log.as_deref(),
+        chunks.len(),
+        inf.frames,
+        completed_frames,
+        Arc::clone(&s.completed),
+        Arc::clone(&s.frames_done),
+    )
+    .map_err(|e| eprintln!("Warning: cannot write progress log: {e}"))
+    .ok()
 }
 
 #[cfg(feature = "vship")]
@@ -662,7 +1365,10 @@ pub struct ProbeConfig<'a> {
 }
 
 #[cfg(feature = "vship")]
-pub fn encode_single_probe(config: &ProbeConfig, prog: Option<&Arc<ProgsTrack>>) {
+pub fn encode_single_probe(
+    config: &ProbeConfig,
+    prog: Option<&Arc<ProgsTrack>>,
+) -> Result<(), XavError> {
     let output = config.work_dir.join("split").join(config.probe_name);
     let enc_cfg = EncConfig {
         inf: config.inf,
@@ -670,59 +1376,107 @@ pub fn encode_single_probe(config: &ProbeConfig, prog: Option<&Arc<ProgsTrack>>)
         crf: config.crf,
         output: &output,
         grain_table: config.grain_table,
+        meta: chunk_meta(config.inf, config.idx),
+        pass: None,
     };
-    let mut cmd = make_enc_cmd(&enc_cfg, false, config.inf.width, config.inf.height);
-    let mut child = cmd.spawn().unwrap_or_else(|_| std::process::exit(1));
-
-    if let Some(p) = prog
-        && let Some(stderr) = child.stderr.take()
-    {
-        p.watch_enc(stderr, config.idx, false, config.crf_score);
-    }
-
+    crate::progs::chunk_start(config.idx, config.crf_score);
     let mut buf = Some(vec![0u8; calc_10bit_size(config.inf)]);
     let frame_size = config.yuv_frames.len() / config.frame_count;
-    write_frames(
-        &mut child,
-        config.yuv_frames,
-        frame_size,
-        config.frame_count,
-        config.inf,
-        &mut buf,
-    );
-    child.wait().unwrap();
+    let mut retried = false;
+
+    loop {
+        let mut cmd = make_enc_cmd(&enc_cfg, false, config.inf.width, config.inf.height);
+        let mut child = spawn_enc(&mut cmd)?;
+
+        if let Some(p) = prog
+            && let Some(stderr) = child.stderr.take()
+        {
+            p.watch_enc(stderr, config.idx, false, config.crf_score);
+        } else if let Some(stderr) = child.stderr.take() {
+            watch_warnings(stderr, config.idx);
+        }
+
+        write_frames(
+            &mut child,
+            config.yuv_frames,
+            frame_size,
+            config.frame_count,
+            config.inf,
+            &mut buf,
+        );
+
+        let status = child.wait().unwrap();
+        if status.success() {
+            return Ok(());
+        }
+        enc_failed(config.idx, status, &mut retried)?;
+    }
 }
 
 #[cfg(feature = "vship")]
 fn create_tq_worker(
     inf: &VidInf,
-    use_cvvdp: bool,
-    use_butteraugli: bool,
-) -> crate::vship::VshipProcessor {
+    (ssimu2, cvvdp, butteraugli): (bool, bool, bool),
+    use_xpsnr: bool,
+    devices: &[i32],
+    start: usize,
+    metric_res: Option<u32>,
+) -> Result<crate::vship::VshipProcessor, XavError> {
+    if use_xpsnr {
+        return Ok(crate::vship::VshipProcessor::xpsnr(inf.width, inf.height, inf.is_10bit()));
+    }
+
     let fps = inf.fps_num as f32 / inf.fps_den as f32;
-    crate::vship::VshipProcessor::new(
+    let target =
+        metric_res.filter(|&h| h < inf.height).map(|h| ((inf.width * h / inf.height) & !1, h & !1));
+    let mut last_err = String::from("No VSHIP device available");
+
+    for i in 0..devices.len() {
+        let device = devices[(start + i) % devices.len()];
+        match crate::vship::VshipProcessor::new(
+            inf.width,
+            inf.height,
+            target,
+            inf.is_10bit(),
+            inf.matrix_coefficients,
+            inf.transfer_characteristics,
+            inf.color_primaries,
+            inf.color_range,
+            inf.chroma_sample_position,
+            fps,
+            (ssimu2, cvvdp, butteraugli),
+            device,
+        ) {
+            Ok(vs) => return Ok(vs),
+            Err(e) => last_err = e.to_string(),
+        }
+    }
+
+    if cvvdp || butteraugli {
+        return Err(XavError::Vship(last_err));
+    }
+
+    static WARN: std::sync::Once = std::sync::Once::new();
+    WARN.call_once(|| {
+        eprintln!("Warning: {last_err}, computing SSIMULACRA2 on the CPU (much slower)");
+    });
+    Ok(crate::vship::VshipProcessor::cpu(
         inf.width,
         inf.height,
-        inf.is_10bit,
+        inf.is_10bit(),
         inf.matrix_coefficients,
-        inf.transfer_characteristics,
-        inf.color_primaries,
         inf.color_range,
-        inf.chroma_sample_position,
-        fps,
-        use_cvvdp,
-        use_butteraugli,
-    )
-    .unwrap()
+    ))
 }
 
 #[cfg(feature = "vship")]
 struct TQChunkConfig<'a> {
-    chunks: &'a [Chunk],
     inf: &'a VidInf,
     params: &'a str,
+    final_params: Option<&'a str>,
     tq: &'a str,
     qp: &'a str,
+    max_rounds: usize,
     work_dir: &'a Path,
     prog: Option<&'a Arc<ProgsTrack>>,
     probe_info: &'a crate::tq::ProbeInfoMap,
@@ -731,6 +1485,32 @@ struct TQChunkConfig<'a> {
     metric_mode: &'a str,
     use_cvvdp: bool,
     use_butteraugli: bool,
+    use_xpsnr: bool,
+    crf_step: f64,
+    guard: Option<crate::tq::Guard>,
+    stride: usize,
+    cache: &'a crate::tq::ProbeCache,
+    gate: &'a crate::tq::MetricGate,
+    threads: i32,
+    keep_probes: bool,
+}
+
+#[cfg(feature = "vship")]
+fn remove_probes(work_dir: &Path, done: &HashSet<usize>) {
+    let Ok(entries) = std::fs::read_dir(work_dir.join("split")) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.ends_with(".ivf")
+            && name
+                .split('_')
+                .next()
+                .and_then(|i| i.parse().ok())
+                .is_some_and(|i| done.contains(&i))
+        {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
 }
 
 #[cfg(feature = "vship")]
@@ -739,9 +1519,9 @@ fn process_tq_chunk(
     config: &TQChunkConfig,
     vship: &crate::vship::VshipProcessor,
     logger: Option<&crate::tq::ProbeLogger>,
-) {
+) -> Result<(), XavError> {
     let mut ctx = crate::tq::QualityContext {
-        chunk: &config.chunks[data.idx],
+        idx: data.idx,
         yuv_frames: &data.frames,
         frame_count: data.frame_count,
         inf: config.inf,
@@ -752,39 +1532,74 @@ fn process_tq_chunk(
         grain_table: config.grain_table,
         use_cvvdp: config.use_cvvdp,
         use_butteraugli: config.use_butteraugli,
+        use_xpsnr: config.use_xpsnr,
+        crf_step: config.crf_step,
+        guard: config.guard,
+        stride: config.stride,
+        cache: config.cache,
+        gate: config.gate,
+        scores: std::collections::HashMap::new(),
+        threads: config.threads,
     };
 
-    if let Some(best) = crate::tq::find_target_quality(
+    if let Some((mut best, crf)) = crate::tq::find_target_quality(
         &mut ctx,
         config.tq,
         config.qp,
+        config.max_rounds,
         config.probe_info,
         config.metric_mode,
         logger,
-    ) {
+    )? {
+        if let Some(params) = config.final_params {
+            best = format!("{:04}_final.ivf", data.idx);
+            encode_single_probe(
+                &ProbeConfig {
+                    yuv_frames: &data.frames,
+                    frame_count: data.frame_count,
+                    inf: config.inf,
+                    params,
+                    crf: crf as f32,
+                    probe_name: &best,
+                    work_dir: config.work_dir,
+                    idx: data.idx,
+                    crf_score: None,
+                    grain_table: config.grain_table,
+                },
+                config.prog,
+            )?;
+        }
+
         let src = config.work_dir.join("split").join(&best);
         let dst = config.work_dir.join("encode").join(format!("{:04}.ivf", data.idx));
-        std::fs::copy(&src, &dst).unwrap();
+        std::fs::copy(&src, &dst).map_err(|e| {
+            XavError::Encode(format!("Chunk {:04}: cannot copy {best}: {e}", data.idx))
+        })?;
+        if !config.keep_probes {
+            remove_probes(config.work_dir, &HashSet::from([data.idx]));
+        }
 
         if let Some(s) = config.stats {
             let meta = std::fs::metadata(&dst).unwrap();
             let comp = ChunkComp { idx: data.idx, frames: data.frame_count, size: meta.len() };
-            s.frames_done.fetch_add(data.frames.len(), Ordering::Relaxed);
+            s.frames_done.fetch_add(data.frame_count, Ordering::Relaxed);
             s.completed.fetch_add(1, Ordering::Relaxed);
             s.add_completion(comp, config.work_dir);
         }
     }
+    Ok(())
 }
 
 #[cfg(feature = "vship")]
 fn encode_tq(
     chunks: &[Chunk],
+    feed: Option<Receiver<Chunk>>,
     inf: &VidInf,
     args: &crate::Args,
     idx: &Arc<VidIdx>,
     work_dir: &Path,
     grain_table: Option<&PathBuf>,
-) {
+) -> Result<(), XavError> {
     let resume_data = if args.resume {
         get_resume(work_dir).unwrap_or(ResumeInf { chnks_done: Vec::new() })
     } else {
@@ -794,14 +1609,19 @@ fn encode_tq(
     let skip_indices: HashSet<usize> = resume_data.chnks_done.iter().map(|c| c.idx).collect();
     let completed_count = skip_indices.len();
     let completed_frames: usize = resume_data.chnks_done.iter().map(|c| c.frames).sum();
+    if !args.keep_workdir && !skip_indices.is_empty() {
+        remove_probes(work_dir, &skip_indices);
+    }
 
-    let stats = if args.quiet {
+    let stats = if args.quiet && args.log.is_none() && args.progress_socket.is_none() {
         None
     } else {
         Some(Arc::new(WorkerStats::new(completed_count, completed_frames, resume_data)))
     };
 
-    let prog = stats.as_ref().map(|s| {
+    let log = start_log(args, chunks, inf, completed_frames, stats.as_ref());
+
+    let prog = stats.as_ref().filter(|_| !args.quiet).map(|s| {
         Arc::new(ProgsTrack::new(
             chunks,
             inf,
@@ -815,28 +1635,56 @@ fn encode_tq(
     let probe_info = Arc::new(std::sync::Mutex::new(std::collections::HashMap::new()));
     let logger = Arc::new(std::sync::Mutex::new(Vec::new()));
 
+    let cache_key = format!(
+        "{} | {:?} | {} | {} | {} | {:?} | {:?} | {:?} | {:?} | {:?} | {}",
+        args.params,
+        args.probe_preset,
+        args.target_quality.as_deref().unwrap_or_default(),
+        args.qp_range.as_deref().unwrap_or_default(),
+        args.metric_mode,
+        args.crop,
+        args.noise,
+        args.grain_table,
+        args.metric_res,
+        args.guard,
+        args.metric_stride
+    );
+    let cache = Arc::new(crate::tq::ProbeCache::open(work_dir, &cache_key, args.resume));
+    let gate = Arc::new(crate::tq::MetricGate::new(args.metric_workers));
+
     let (tx, rx) = bounded::<ChunkData>(0);
     let rx = Arc::new(rx);
+    let pool = Arc::new(BufPool::new(args.pool, args.mem));
 
-    let crop = args.crop.unwrap_or((0, 0));
+    let crop = args.crop.unwrap_or_default();
+    let max_len = max_chunk_len(chunks, inf, args);
+    let queue = chunk_queue(chunks, feed, args.sequential, skip_indices);
 
     let dec = {
-        let c = chunks.to_vec();
         let i = Arc::clone(idx);
         let inf = inf.clone();
+        let pool = Arc::clone(&pool);
+        let threads = args.threads;
         thread::spawn(move || {
-            decode_chunks(&c, &i, &inf, &tx, &skip_indices, crop);
+            decode_chunks(&queue, max_len, (&i, threads), &inf, &tx, crop, &pool);
         })
     };
 
+    let devices: Arc<[i32]> = args
+        .gpu
+        .clone()
+        .map_or_else(|| (0..crate::vship::device_count().max(1)).collect(), Into::into);
+
     let mut workers = Vec::new();
-    for _ in 0..args.worker {
+    for worker_idx in 0..args.worker {
+        let devices = Arc::clone(&devices);
         let probe_info = Arc::clone(&probe_info);
         let logger = Arc::clone(&logger);
         let rx = Arc::clone(&rx);
-        let c = chunks.to_vec();
         let inf = inf.clone();
         let params = args.params.clone();
+        let probe_params =
+            args.probe_preset.map(|p| set_param(&params, "--preset", &p.to_string()));
         let tq = args.target_quality.clone().unwrap();
         let qp = args.qp_range.clone().unwrap();
         let stats = stats.clone();
@@ -844,40 +1692,79 @@ fn encode_tq(
         let wd = work_dir.to_path_buf();
         let grain = grain_table.cloned();
         let metric_mode = args.metric_mode.clone();
-
-        let use_cvvdp = {
+        let max_rounds = args.max_rounds;
+        let crf_step = args.crf_step;
+        let guard = args.guard;
+        let metric_res = args.metric_res;
+        let stride = args.metric_stride;
+        let pin = args.pin.then_some(args.worker);
+        let threads = args.threads.try_into().unwrap_or(8);
+        let pool = Arc::clone(&pool);
+        let cache = Arc::clone(&cache);
+        let gate = Arc::clone(&gate);
+        let keep_probes = args.keep_workdir;
+
+        let use_xpsnr = args.xpsnr;
+
+        let use_cvvdp = !use_xpsnr && {
             let tq_parts: Vec<f64> = tq.split('-').filter_map(|s| s.parse().ok()).collect();
             let target = f64::midpoint(tq_parts[0], tq_parts[1]);
             target > 8.0 && target <= 10.0
         };
 
-        let use_butteraugli = {
+        let use_butteraugli = !use_xpsnr && {
             let tq_parts: Vec<f64> = tq.split('-').filter_map(|s| s.parse().ok()).collect();
             let target = f64::midpoint(tq_parts[0], tq_parts[1]);
             target < 8.0
         };
 
         workers.push(thread::spawn(move || {
+            if let Some(n) = pin {
+                pin_worker(worker_idx, n);
+            }
             let mut init = false;
             let mut vship = None;
             let mut working_inf = inf.clone();
 
             while let Ok(data) = rx.recv() {
+                if crate::STOP.load(Ordering::Relaxed) {
+                    pool.put(data.frames);
+                    continue;
+                }
                 if !init {
                     working_inf.width = data.width;
                     working_inf.height = data.height;
 
-                    let vs = create_tq_worker(&working_inf, use_cvvdp, use_butteraugli);
-                    vship = Some(vs);
+                    let metrics = (
+                        (!use_cvvdp && !use_butteraugli)
+                            || matches!(guard, Some(crate::tq::Guard::Ssimu2(_))),
+                        use_cvvdp,
+                        use_butteraugli || matches!(guard, Some(crate::tq::Guard::Butteraugli(_))),
+                    );
+                    match create_tq_worker(
+                        &working_inf,
+                        metrics,
+                        use_xpsnr,
+                        &devices,
+                        worker_idx,
+                        metric_res,
+                    ) {
+                        Ok(vs) => vship = Some(vs),
+                        Err(e) => {
+                            pool.put(data.frames);
+                            return abort_on_err(Err(e));
+                        }
+                    }
                     init = true;
                 }
 
                 let config = TQChunkConfig {
-                    chunks: &c,
                     inf: &working_inf,
-                    params: &params,
+                    params: probe_params.as_deref().unwrap_or(&params),
+                    final_params: probe_params.as_ref().map(|_| params.as_str()),
                     tq: &tq,
                     qp: &qp,
+                    max_rounds,
                     work_dir: &wd,
                     prog: prog.as_ref(),
                     probe_info: &probe_info,
@@ -886,26 +1773,43 @@ fn encode_tq(
                     metric_mode: &metric_mode,
                     use_cvvdp,
                     use_butteraugli,
+                    use_xpsnr,
+                    crf_step,
+                    guard,
+                    stride,
+                    cache: &cache,
+                    gate: &gate,
+                    threads,
+                    keep_probes,
                 };
 
-                process_tq_chunk(&data, &config, vship.as_ref().unwrap(), Some(&logger));
+                let done = process_tq_chunk(&data, &config, vship.as_ref().unwrap(), Some(&logger));
+                pool.put(data.frames);
+                abort_on_err(done)?;
             }
+            Ok(())
         }));
     }
 
+    drop(rx);
     dec.join().unwrap();
-    for w in workers {
-        w.join().unwrap();
-    }
+    let failed = workers.into_iter().filter_map(|h| h.join().unwrap().err()).next();
     if let Some(p) = prog {
         p.final_update();
     }
+    if let Some(l) = log {
+        l.finish();
+    }
 
-    write_tq_log(&logger, work_dir, &args.input);
+    write_tq_log(&logger, work_dir, &args.input, args.max_rounds);
+    if let Some(ref path) = args.probe_log {
+        write_probe_log(&logger, path, args.max_rounds);
+    }
+    failed.map_or(Ok(()), Err)
 }
 
 #[cfg(feature = "vship")]
-fn write_tq_log(logger: &crate::tq::ProbeLogger, work_dir: &Path, input: &Path) {
+fn write_tq_log(logger: &crate::tq::ProbeLogger, work_dir: &Path, input: &Path, max_rounds: usize) {
     use std::collections::HashMap;
     use std::fmt::Write;
 
@@ -933,7 +1837,7 @@ fn write_tq_log(logger: &crate::tq::ProbeLogger, work_dir: &Path, input: &Path)
     let total = logs.len();
     let avg_probes = logs.iter().map(|l| l.probes.len()).sum::<usize>() as f64 / total as f64;
 
-    let in_range = logs.iter().filter(|l| l.round < 10).count();
+    let in_range = logs.iter().filter(|l| l.round <= max_rounds).count();
     let out_range = total - in_range;
 
     let mut round_counts: HashMap<usize, usize> = HashMap::new();
@@ -982,3 +1886,60 @@ fn write_tq_log(logger: &crate::tq::ProbeLogger, work_dir: &Path, input: &Path)
 
     std::fs::write(log_path, content).ok();
 }
+
+#[cfg(feature = "vship")]
+fn write_probe_log(logger: &crate::tq::ProbeLogger, path: &Path, max_rounds: usize) {
+    use std::fmt::Write;
+
+    let mut logs = logger.lock().unwrap();
+    logs.sort_by_key(|l| l.chunk_idx);
+
+    let json = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let mut content = String::new();
+
+    if json {
+        content.push_str("[\n");
+        for (i, log) in logs.iter().enumerate() {
+            let probes = log
+                .probes
+                .iter()
+                .map(|(c, s)| format!("{{\"crf\":{c:.2},\"score\":{s:.4}}}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = writeln!(
+                content,
+                "  {{\"chunk\":{},\"probes\":[{probes}],\"final_crf\":{:.2},\"final_score\":{:.4},\
+                 \"rounds\":{},\"in_range\":{}}}{}",
+                log.chunk_idx,
+                log.final_crf,
+                log.final_score,
+                log.probes.len(),
+                log.round <= max_rounds,
+                if i + 1 < logs.len() { "," } else { "" }
+            );
+        }
+        content.push_str("]\n");
+    } else {
+        content.push_str("chunk,probes,final_crf,final_score,rounds,in_range\n");
+        for log in logs.iter() {
+            let probes = log
+                .probes
+                .iter()
+                .map(|(c, s)| format!("{c:.2}:{s:.4}"))
+                .collect::<Vec<_>>()
+                .join(";");
+            let _ = writeln!(
+                content,
+                "{},{probes},{:.2},{:.4},{},{}",
+                log.chunk_idx,
+                log.final_crf,
+                log.final_score,
+                log.probes.len(),
+                log.round <= max_rounds
+            );
+        }
+    }
+    drop(logs);
+
+    std::fs::write(path, content).ok();
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::Ordering;
+
+use crate::chunk::Container;
+use crate::error::XavError;
+
+const MIN_SVT: (u32, u32, u32) = (1, 0, 0);
+
+fn run(tool: &str, arg: &str) -> Result<String, XavError> {
+    let out = Command::new(tool)
+        .arg(arg)
+        .output()
+        .map_err(|e| XavError::Tool(format!("`{tool}` is required but could not be run: {e}")))?;
+    let mut text = String::from_utf8_lossy(&out.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&out.stderr));
+    Ok(text)
+}
+
+pub trait ShowCmd {
+    fn show(&mut self) -> &mut Self;
+}
+
+impl ShowCmd for Command {
+    fn show(&mut self) -> &mut Self {
+        if crate::PRINT_CMDS.load(Ordering::Relaxed) {
+            eprintln!("{}", cmd_line(self));
+        }
+        self
+    }
+}
+
+pub fn cmd_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| {
+            let a = a.to_string_lossy();
+            if !a.is_empty()
+                && a.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c))
+            {
+                a.into_owned()
+            } else {
+                format!("'{}'", a.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn free_space(path: &Path) -> Option<u64> {
+    let dir = path.ancestors().find(|p| p.as_os_str().is_empty() || p.exists())?;
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let c_path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    unsafe {
+        let mut st: libc::statvfs = std::mem::zeroed();
+        (libc::statvfs(c_path.as_ptr(), &raw mut st) == 0).then(|| st.f_bavail * st.f_frsize)
+    }
+}
+
+pub fn dir_size(path: &Path) -> u64 {
+    fs::read_dir(path).into_iter().flatten().flatten().fold(0, |sum, e| match e.metadata() {
+        Ok(m) if m.is_dir() => sum + dir_size(&e.path()),
+        Ok(m) => sum + m.len(),
+        Err(_) => sum,
+    })
+}
+
+fn parse_version(text: &str) -> Option<(u32, u32, u32)> {
+    let word = text.split_whitespace().find(|w| {
+        w.strip_prefix('v').is_some_and(|v| v.starts_with(|c: char| c.is_ascii_digit()))
+    })?;
+    let mut nums = word[1..]
+        .split(|c: char| !c.is_ascii_digit())
+        .take(3)
+        .map(|n| n.parse::<u32>().unwrap_or(0));
+    Some((nums.next()?, nums.next().unwrap_or(0), nums.next().unwrap_or(0)))
+}
+
+fn check_svt(args: &crate::Args, container: Container) -> Result<(), XavError> {
+    let text = run("SvtAv1EncApp", "--version")?;
+    let (ma, mi, pa) = parse_version(&text).ok_or_else(|| {
+        XavError::Tool(format!("Cannot read the SvtAv1EncApp version from `{}`", text.trim()))
+    })?;
+    if (ma, mi, pa) < MIN_SVT {
+        return Err(XavError::Tool(format!(
+            "SvtAv1EncApp v{ma}.{mi}.{pa} is too old, v{}.{}.{} or newer is required",
+            MIN_SVT.0, MIN_SVT.1, MIN_SVT.2
+        )));
+    }
+
+    let help = run("SvtAv1EncApp", "--help")?;
+    let mut flags = vec![
+        "--progress",
+        "--color-primaries",
+        "--transfer-characteristics",
+        "--matrix-coefficients",
+        "--color-range",
+        "--chroma-sample-position",
+    ];
+    if args.noise.is_some() {
+        flags.push("--fgs-table");
+    }
+    if args.two_pass {
+        flags.push("--stats");
+    }
+    if container == Container::Avif {
+        flags.push("--avif");
+    }
+    for kind in &args.dyn_meta {
+        flags.push(kind.svt_flag());
+    }
+    if let Some(flag) = flags.iter().find(|f| !help.contains(*f)) {
+        return Err(XavError::Tool(format!(
+            "SvtAv1EncApp v{ma}.{mi}.{pa} does not support `{flag}`"
+        )));
+    }
+    Ok(())
+}
+
+pub fn preflight(args: &crate::Args, container: Container) -> Result<(), XavError> {
+    if args.audio_only.is_none() {
+        check_svt(args, container)?;
+    }
+
+    run("mkvmerge", "--version")?;
+    for kind in &args.dyn_meta {
+        run(kind.tool(), "--version")?;
+    }
+    if args.audio.is_some()
+        || matches!(container, Container::Mp4 | Container::Avif)
+        || !args.dyn_meta.is_empty()
+    {
+        run("ffmpeg", "-version")?;
+    }
+    if args.audio.is_some() || args.dyn_meta.contains(&crate::dynmeta::DynMeta::DolbyVision) {
+        run("ffprobe", "-version")?;
+    }
+
+    Ok(())
+}
@@ -1,7 +1,10 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
 
-use crate::chunk::Chunk;
+use crate::error::XavError;
 use crate::ffms::VidInf;
 use crate::interp::{akima, lerp, natural_cubic, pchip};
 
@@ -12,6 +15,33 @@ struct Probe {
     crf: f64,
     score: f64,
     frame_scores: Vec<f64>,
+    guard: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Guard {
+    Ssimu2(f64),
+    Butteraugli(f64),
+}
+
+impl Guard {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let err = || format!("Guard must be `ssimu2:<min>` or `butter:<max>`, got `{s}`");
+        let (metric, value) = s.split_once(':').ok_or_else(err)?;
+        let value: f64 = value.parse().map_err(|_| err())?;
+        match metric {
+            "ssimu2" => Ok(Self::Ssimu2(value)),
+            "butter" => Ok(Self::Butteraugli(value)),
+            _ => Err(err()),
+        }
+    }
+
+    const fn passes(self, score: f64) -> bool {
+        match self {
+            Self::Ssimu2(min) => score >= min,
+            Self::Butteraugli(max) => score <= max,
+        }
+    }
 }
 
 pub struct ProbeLog {
@@ -24,22 +54,76 @@ pub struct ProbeLog {
 
 pub type ProbeLogger = Arc<std::sync::Mutex<Vec<ProbeLog>>>;
 
+pub struct ProbeCache {
+    seeds: HashMap<usize, Vec<Probe>>,
+    writer: Mutex<Option<fs::File>>,
+}
+
+impl ProbeCache {
+    pub fn open(work_dir: &Path, key: &str, resume: bool) -> Self {
+        let path = work_dir.join("probes.txt");
+        let content =
+            if resume { fs::read_to_string(&path).unwrap_or_default() } else { String::new() };
+        let mut lines = content.lines();
+        let mut seeds: HashMap<usize, Vec<Probe>> = HashMap::new();
+
+        if lines.next() == Some(key) {
+            for line in lines {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if (4..=5).contains(&parts.len())
+                    && let (Ok(idx), Ok(crf), Ok(score)) =
+                        (parts[0].parse(), parts[1].parse(), parts[2].parse())
+                {
+                    let frame_scores = parts[3].split(',').filter_map(|v| v.parse().ok()).collect();
+                    let guard = parts.get(4).and_then(|g| g.parse().ok());
+                    seeds.entry(idx).or_default().push(Probe { crf, score, frame_scores, guard });
+                }
+            }
+        } else {
+            let _ = fs::write(&path, format!("{key}\n"));
+        }
+
+        let writer = fs::OpenOptions::new().append(true).open(&path).ok();
+        Self { seeds, writer: Mutex::new(writer) }
+    }
+
+    fn seeds(&self, idx: usize, work_dir: &Path) -> Vec<Probe> {
+        self.seeds.get(&idx).map_or_else(Vec::new, |probes| {
+            probes
+                .iter()
+                .filter(|p| work_dir.join("split").join(probe_name(idx, p.crf)).exists())
+                .cloned()
+                .collect()
+        })
+    }
+
+    fn add(&self, idx: usize, probe: &Probe) {
+        let scores = probe.frame_scores.iter().map(f64::to_string).collect::<Vec<_>>().join(",");
+        let guard = probe.guard.map(|g| format!(" {g}")).unwrap_or_default();
+        let line = format!("{idx} {} {} {scores}{guard}\n", probe.crf, probe.score);
+        if let Some(f) = self.writer.lock().unwrap().as_mut() {
+            let _ = f.write_all(line.as_bytes());
+        }
+    }
+}
+
 struct TQConfig {
     target: f64,
     tolerance: f64,
     min_crf: f64,
     max_crf: f64,
+    max_rounds: usize,
 }
 
 impl TQConfig {
-    fn new(tq_range: &str, qp_range: &str) -> Self {
+    fn new(tq_range: &str, qp_range: &str, max_rounds: usize) -> Self {
         let tq_parts: Vec<f64> = tq_range.split('-').filter_map(|s| s.parse().ok()).collect();
         let qp_parts: Vec<f64> = qp_range.split('-').filter_map(|s| s.parse().ok()).collect();
 
         let target = f64::midpoint(tq_parts[0], tq_parts[1]);
         let tolerance = (tq_parts[1] - tq_parts[0]) / 2.0;
 
-        Self { target, tolerance, min_crf: qp_parts[0], max_crf: qp_parts[1] }
+        Self { target, tolerance, min_crf: qp_parts[0], max_crf: qp_parts[1], max_rounds }
     }
 
     fn in_range(&self, score: f64) -> bool {
@@ -51,8 +135,33 @@ impl TQConfig {
     }
 }
 
+pub struct MetricGate {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl MetricGate {
+    pub const fn new(slots: usize) -> Self {
+        Self { free: Mutex::new(slots), freed: Condvar::new() }
+    }
+
+    fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap();
+        }
+        *free -= 1;
+        drop(free);
+
+        let out = f();
+        *self.free.lock().unwrap() += 1;
+        self.freed.notify_one();
+        out
+    }
+}
+
 pub struct QualityContext<'a> {
-    pub chunk: &'a Chunk,
+    pub idx: usize,
     pub yuv_frames: &'a [u8],
     pub frame_count: usize,
     pub inf: &'a VidInf,
@@ -63,18 +172,75 @@ pub struct QualityContext<'a> {
     pub grain_table: Option<&'a Path>,
     pub use_cvvdp: bool,
     pub use_butteraugli: bool,
+    pub use_xpsnr: bool,
+    pub crf_step: f64,
+    pub guard: Option<Guard>,
+    pub stride: usize,
+    pub cache: &'a ProbeCache,
+    pub gate: &'a MetricGate,
+    pub scores: HashMap<i64, (f64, Vec<f64>, Option<f64>)>,
+    pub threads: i32,
 }
 
-fn round_crf(crf: f64) -> f64 {
-    (crf * 4.0).round() / 4.0
+fn round_crf(crf: f64, step: f64) -> f64 {
+    (crf / step).round() * step
 }
 
-fn binary_search(min: f64, max: f64) -> f64 {
-    round_crf(f64::midpoint(min, max))
+fn binary_search(min: f64, max: f64, step: f64) -> f64 {
+    round_crf(f64::midpoint(min, max), step)
 }
 
-fn encode_probe(ctx: &QualityContext, crf: f64, last_score: Option<f64>) -> String {
-    let probe_name = format!("{:04}_{:.2}.ivf", ctx.chunk.idx, crf);
+fn crf_key(crf: f64, step: f64) -> i64 {
+    (crf / step).round() as i64
+}
+
+fn next_crf(ctx: &QualityContext, want: f64, min: f64, max: f64) -> Option<f64> {
+    let step = ctx.crf_step;
+    let free = |crf: f64| !ctx.scores.contains_key(&crf_key(crf, step));
+    let mid = binary_search(min, max, step).clamp(min, max);
+    if free(want) {
+        return Some(want);
+    }
+    if free(mid) {
+        return Some(mid);
+    }
+    let steps = ((max - min) / step).round() as usize;
+    (0..=steps)
+        .map(|i| round_crf(min + i as f64 * step, step))
+        .filter(|&crf| crf >= min && crf <= max && free(crf))
+        .min_by(|a, b| (a - mid).abs().total_cmp(&(b - mid).abs()))
+}
+
+fn probe_score(
+    ctx: &mut QualityContext,
+    crf: f64,
+    last_score: Option<f64>,
+    metric_mode: &str,
+) -> Result<(f64, Vec<f64>, Option<f64>), XavError> {
+    let key = crf_key(crf, ctx.crf_step);
+    if let Some(hit) = ctx.scores.get(&key) {
+        return Ok(hit.clone());
+    }
+
+    let probe_name = encode_probe(ctx, crf, last_score)?;
+    let probe_path = ctx.work_dir.join("split").join(&probe_name);
+    let gate = ctx.gate;
+    let result =
+        gate.run(|| measure_quality(ctx, &probe_path, crf as f32, last_score, metric_mode))?;
+    ctx.scores.insert(key, result.clone());
+    Ok(result)
+}
+
+fn probe_name(idx: usize, crf: f64) -> String {
+    format!("{idx:04}_{crf:.2}.ivf")
+}
+
+fn encode_probe(
+    ctx: &QualityContext,
+    crf: f64,
+    last_score: Option<f64>,
+) -> Result<String, XavError> {
+    let probe_name = probe_name(ctx.idx, crf);
     crate::svt::encode_single_probe(
         &crate::svt::ProbeConfig {
             yuv_frames: ctx.yuv_frames,
@@ -84,13 +250,13 @@ fn encode_probe(ctx: &QualityContext, crf: f64, last_score: Option<f64>) -> Stri
             crf: crf as f32,
             probe_name: &probe_name,
             work_dir: ctx.work_dir,
-            idx: ctx.chunk.idx,
+            idx: ctx.idx,
             crf_score: Some((crf as f32, last_score)),
             grain_table: ctx.grain_table,
         },
         ctx.prog,
-    );
-    probe_name
+    )?;
+    Ok(probe_name)
 }
 
 fn measure_quality(
@@ -99,38 +265,43 @@ fn measure_quality(
     crf: f32,
     last_score: Option<f64>,
     metric_mode: &str,
-) -> (f64, Vec<f64>) {
+) -> Result<(f64, Vec<f64>, Option<f64>), XavError> {
+    let vship_err = |e: Box<dyn std::error::Error>| XavError::Vship(e.to_string());
+    let probe_err =
+        |e: Box<dyn std::error::Error>| XavError::Encode(format!("{}: {e}", probe_path.display()));
     if ctx.use_cvvdp {
-        ctx.vship.reset_cvvdp().unwrap();
+        ctx.vship.reset_cvvdp().map_err(vship_err)?;
+    }
+    if ctx.use_xpsnr {
+        ctx.vship.reset_xpsnr();
     }
 
-    let idx = crate::ffms::VidIdx::new(probe_path, true).unwrap();
-    let threads =
-        std::thread::available_parallelism().map_or(8, |n| n.get().try_into().unwrap_or(8));
-    let output_source = crate::ffms::thr_vid_src(&idx, threads).unwrap();
+    let idx = crate::ffms::VidIdx::new(probe_path, true).map_err(probe_err)?;
+    let output_source = crate::ffms::thr_vid_src(&idx, ctx.threads).map_err(probe_err)?;
 
     let mut scores = Vec::with_capacity(ctx.frame_count);
+    let mut guard_scores = Vec::new();
 
     let start = std::time::Instant::now();
     let frame_size = ctx.yuv_frames.len() / ctx.frame_count;
-    let tot = ctx.frame_count;
+    let tot = ctx.frame_count.div_ceil(ctx.stride);
 
     let mut unpacked_buf = vec![0u8; crate::ffms::calc_10bit_size(ctx.inf)];
 
-    for frame_idx in 0..ctx.frame_count {
+    for (n, frame_idx) in (0..ctx.frame_count).step_by(ctx.stride).enumerate() {
         let frame_start = frame_idx * frame_size;
         let frame_end = frame_start + frame_size;
         let input_yuv_packed = &ctx.yuv_frames[frame_start..frame_end];
-        let output_frame = crate::ffms::get_frame(output_source, frame_idx).unwrap();
+        let output_frame = crate::ffms::get_frame(output_source, frame_idx).map_err(probe_err)?;
 
-        let input_yuv: &[u8] = if ctx.inf.is_10bit {
+        let input_yuv: &[u8] = if ctx.inf.is_10bit() {
             crate::ffms::unpack_10bit(input_yuv_packed, &mut unpacked_buf);
             &unpacked_buf
         } else {
             input_yuv_packed
         };
 
-        let pixel_size = if ctx.inf.is_10bit { 2 } else { 1 };
+        let pixel_size = if ctx.inf.is_10bit() { 2 } else { 1 };
         let y_size = (ctx.inf.width * ctx.inf.height) as usize * pixel_size;
         let uv_size = y_size / 4;
 
@@ -154,7 +325,11 @@ fn measure_quality(
             ]
         };
 
-        let score = if ctx.use_butteraugli {
+        let score = if ctx.use_xpsnr {
+            ctx.vship
+                .compute_xpsnr(input_planes, output_planes, input_line_sizes, output_line_sizes)
+                .map_err(vship_err)?
+        } else if ctx.use_butteraugli {
             ctx.vship
                 .compute_butteraugli(
                     input_planes,
@@ -162,11 +337,11 @@ fn measure_quality(
                     input_line_sizes,
                     output_line_sizes,
                 )
-                .unwrap()
+                .map_err(vship_err)?
         } else if ctx.use_cvvdp {
             ctx.vship
                 .compute_cvvdp(input_planes, output_planes, input_line_sizes, output_line_sizes)
-                .unwrap()
+                .map_err(vship_err)?
         } else {
             ctx.vship
                 .compute_ssimulacra2(
@@ -175,14 +350,33 @@ fn measure_quality(
                     input_line_sizes,
                     output_line_sizes,
                 )
-                .unwrap()
+                .map_err(vship_err)?
         };
         scores.push(score);
 
+        let guard = match ctx.guard {
+            Some(Guard::Ssimu2(_)) => Some(ctx.vship.compute_ssimulacra2(
+                input_planes,
+                output_planes,
+                input_line_sizes,
+                output_line_sizes,
+            )),
+            Some(Guard::Butteraugli(_)) => Some(ctx.vship.compute_butteraugli(
+                input_planes,
+                output_planes,
+                input_line_sizes,
+                output_line_sizes,
+            )),
+            None => None,
+        };
+        if let Some(g) = guard {
+            guard_scores.push(g.map_err(vship_err)?);
+        }
+
         if let Some(p) = ctx.prog {
             let elapsed = start.elapsed().as_secs_f32().max(0.001);
-            let fps = (frame_idx + 1) as f32 / elapsed;
-            p.show_metric(ctx.chunk.idx, frame_idx + 1, tot, fps, crf, last_score);
+            let fps = (n + 1) as f32 / elapsed;
+            p.show_metric(ctx.idx, n + 1, tot, fps, crf, last_score);
         }
     }
 
@@ -190,11 +384,19 @@ fn measure_quality(
 
     let result = if ctx.use_cvvdp {
         scores.last().copied().unwrap_or(0.0)
-    } else if metric_mode == "mean" {
-        scores.iter().sum::<f64>() / scores.len() as f64
-    } else if let Some(percentile_str) = metric_mode.strip_prefix('p') {
+    } else {
+        pool_scores(&mut scores, metric_mode, ctx.use_butteraugli)
+    };
+    let guard = ctx
+        .guard
+        .map(|g| pool_scores(&mut guard_scores, metric_mode, matches!(g, Guard::Butteraugli(_))));
+    Ok((result, scores, guard))
+}
+
+fn pool_scores(scores: &mut [f64], metric_mode: &str, lower_better: bool) -> f64 {
+    if let Some(percentile_str) = metric_mode.strip_prefix('p') {
         let percentile: f64 = percentile_str.parse().unwrap_or(15.0);
-        if ctx.use_butteraugli {
+        if lower_better {
             scores.sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
         } else {
             scores.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
@@ -204,148 +406,191 @@ fn measure_quality(
         scores[..cutoff_idx].iter().sum::<f64>() / cutoff_idx as f64
     } else {
         scores.iter().sum::<f64>() / scores.len() as f64
-    };
-    (result, scores)
+    }
 }
 
-fn interpolate_crf(probes: &[Probe], target: f64, round: usize) -> Option<f64> {
+fn interpolate_crf(probes: &[Probe], target: f64, step: f64) -> Option<f64> {
     let mut sorted = probes.to_vec();
+    sorted.sort_unstable_by(|a, b| {
+        (a.score - target).abs().partial_cmp(&(b.score - target).abs()).unwrap()
+    });
+    sorted.truncate(5);
     sorted.sort_unstable_by(|a, b| a.score.partial_cmp(&b.score).unwrap());
 
-    let n = sorted.len();
-    let x: Vec<f64> = sorted.iter().map(|p| p.score).collect();
-    let y: Vec<f64> = sorted.iter().map(|p| p.crf).collect();
-
-    let result = match round {
-        3 if n >= 2 => lerp(&[x[0], x[1]], &[y[0], y[1]], target),
-        4 if n >= 3 => natural_cubic(&x, &y, target),
-        5 if n >= 4 => pchip(&[x[0], x[1], x[2], x[3]], &[y[0], y[1], y[2], y[3]], target),
-        6 if n >= 5 => {
-            akima(&[x[0], x[1], x[2], x[3], x[4]], &[y[0], y[1], y[2], y[3], y[4]], target)
+    let dir = (sorted[sorted.len() - 1].crf - sorted[0].crf).signum();
+    let mut x: Vec<f64> = Vec::with_capacity(sorted.len());
+    let mut y: Vec<f64> = Vec::with_capacity(sorted.len());
+    for p in &sorted {
+        if let (Some(&lx), Some(&ly)) = (x.last(), y.last())
+            && (p.score <= lx || (p.crf - ly) * dir <= 0.0)
+        {
+            continue;
         }
-        _ => None,
+        x.push(p.score);
+        y.push(p.crf);
+    }
+
+    let n = x.len();
+    if n < 2 || x[n - 1] - x[0] < 1e-6 {
+        return None;
+    }
+
+    let result = match n {
+        2 => lerp(&[x[0], x[1]], &[y[0], y[1]], target),
+        3 => natural_cubic(&x, &y, target),
+        4 => pchip(&[x[0], x[1], x[2], x[3]], &[y[0], y[1], y[2], y[3]], target),
+        _ => akima(&[x[0], x[1], x[2], x[3], x[4]], &[y[0], y[1], y[2], y[3], y[4]], target),
+    };
+
+    result.map(|crf| round_crf(crf, step))
+}
+
+fn in_target(ctx: &QualityContext, config: &TQConfig, probe: &Probe) -> bool {
+    let in_range = if ctx.use_butteraugli {
+        config.in_range_reversed(probe.score)
+    } else {
+        config.in_range(probe.score)
     };
+    in_range && guard_ok(ctx, probe)
+}
+
+fn guard_ok(ctx: &QualityContext, probe: &Probe) -> bool {
+    ctx.guard.is_none_or(|g| probe.guard.is_some_and(|s| g.passes(s)))
+}
+
+fn narrow(
+    ctx: &QualityContext,
+    config: &TQConfig,
+    probe: &Probe,
+    search_min: &mut f64,
+    search_max: &mut f64,
+) {
+    let (crf, score) = (probe.crf, probe.score);
+    if !guard_ok(ctx, probe) {
+        *search_max = search_max.min(crf - ctx.crf_step);
+    } else if ctx.use_butteraugli {
+        if score > config.target + config.tolerance {
+            *search_max = crf - ctx.crf_step;
+        } else if score < config.target - config.tolerance {
+            *search_min = crf + ctx.crf_step;
+        }
+    } else if score < config.target - config.tolerance {
+        *search_max = crf - ctx.crf_step;
+    } else if score > config.target + config.tolerance {
+        *search_min = crf + ctx.crf_step;
+    }
+}
+
+fn record(
+    ctx: &QualityContext,
+    probes: &[Probe],
+    best: &Probe,
+    round: usize,
+    logger: Option<&ProbeLogger>,
+) {
+    if let Some(log) = logger {
+        let mut l = log.lock().unwrap();
+        l.push(ProbeLog {
+            chunk_idx: ctx.idx,
+            probes: probes.iter().map(|p| (p.crf, p.score)).collect(),
+            final_crf: best.crf,
+            final_score: best.score,
+            round,
+        });
+    }
 
-    result.map(round_crf)
+    let mut scores =
+        crate::svt::TQ_SCORES.get_or_init(|| std::sync::Mutex::new(Vec::new())).lock().unwrap();
+    if ctx.use_cvvdp {
+        scores.push((best.score, ctx.frame_count));
+    } else {
+        scores.extend(
+            best.frame_scores
+                .iter()
+                .enumerate()
+                .map(|(i, &s)| (s, ctx.stride.min(ctx.frame_count - i * ctx.stride))),
+        );
+    }
 }
 
 pub fn find_target_quality(
     ctx: &mut QualityContext,
     tq_range: &str,
     qp_range: &str,
+    max_rounds: usize,
     probe_info: &ProbeInfoMap,
     metric_mode: &str,
     logger: Option<&ProbeLogger>,
-) -> Option<String> {
-    let config = TQConfig::new(tq_range, qp_range);
-    let mut probes = Vec::new();
+) -> Result<Option<(String, f64)>, XavError> {
+    let config = TQConfig::new(tq_range, qp_range, max_rounds);
+    let mut probes = ctx.cache.seeds(ctx.idx, ctx.work_dir);
     let mut search_min = config.min_crf;
     let mut search_max = config.max_crf;
 
-    for round in 1..=10 {
-        let crf = if round <= 2 || round > 6 {
-            binary_search(search_min, search_max)
+    for probe in &probes {
+        let key = crf_key(probe.crf, ctx.crf_step);
+        ctx.scores.insert(key, (probe.score, probe.frame_scores.clone(), probe.guard));
+    }
+
+    for (i, probe) in probes.iter().enumerate() {
+        if in_target(ctx, &config, probe) {
+            record(ctx, &probes[..=i], probe, i + 1, logger);
+            return Ok(Some((probe_name(ctx.idx, probe.crf), probe.crf)));
+        }
+        narrow(ctx, &config, probe, &mut search_min, &mut search_max);
+    }
+
+    for round in probes.len() + 1..=config.max_rounds {
+        if search_min > search_max {
+            break;
+        }
+
+        let want = if probes.len() < 2 {
+            binary_search(search_min, search_max, ctx.crf_step)
         } else {
-            interpolate_crf(&probes, config.target, round)
-                .unwrap_or_else(|| binary_search(search_min, search_max))
+            interpolate_crf(&probes, config.target, ctx.crf_step)
+                .unwrap_or_else(|| binary_search(search_min, search_max, ctx.crf_step))
         }
         .clamp(search_min, search_max);
 
-        let last_score_val = probes.last().map(|p| p.score);
-        let probe_name = encode_probe(ctx, crf, last_score_val);
-        let probe_path = ctx.work_dir.join("split").join(&probe_name);
+        let Some(crf) = next_crf(ctx, want, search_min, search_max) else {
+            break;
+        };
 
-        let (score, frame_scores) =
-            measure_quality(ctx, &probe_path, crf as f32, last_score_val, metric_mode);
+        let last_score_val = probes.last().map(|p| p.score);
+        let (score, frame_scores, guard) = probe_score(ctx, crf, last_score_val, metric_mode)?;
+        let probe_name = probe_name(ctx.idx, crf);
 
         {
             let mut info = probe_info.lock().unwrap();
-            info.insert(ctx.chunk.idx, (crf as f32, Some(score)));
+            info.insert(ctx.idx, (crf as f32, Some(score)));
         }
 
-        probes.push(Probe { crf, score, frame_scores });
-
-        let in_range = if ctx.use_butteraugli {
-            config.in_range_reversed(score)
-        } else {
-            config.in_range(score)
-        };
-
-        if in_range {
-            if let Some(log) = logger {
-                let mut l = log.lock().unwrap();
-                l.push(ProbeLog {
-                    chunk_idx: ctx.chunk.idx,
-                    probes: probes.iter().map(|p| (p.crf, p.score)).collect(),
-                    final_crf: crf,
-                    final_score: score,
-                    round,
-                });
-            }
-
-            if ctx.use_cvvdp {
-                crate::svt::TQ_SCORES
-                    .get_or_init(|| std::sync::Mutex::new(Vec::new()))
-                    .lock()
-                    .unwrap()
-                    .push(score);
-            } else {
-                crate::svt::TQ_SCORES
-                    .get_or_init(|| std::sync::Mutex::new(Vec::new()))
-                    .lock()
-                    .unwrap()
-                    .extend_from_slice(&probes.last().unwrap().frame_scores);
-            }
-            return Some(probe_name);
-        }
+        let probe = Probe { crf, score, frame_scores, guard };
+        ctx.cache.add(ctx.idx, &probe);
+        probes.push(probe);
 
-        if ctx.use_butteraugli {
-            if score > config.target + config.tolerance {
-                search_max = crf - 0.25;
-            } else if score < config.target - config.tolerance {
-                search_min = crf + 0.25;
-            }
-        } else if score < config.target - config.tolerance {
-            search_max = crf - 0.25;
-        } else if score > config.target + config.tolerance {
-            search_min = crf + 0.25;
+        let last = probes.last().unwrap();
+        if in_target(ctx, &config, last) {
+            record(ctx, &probes, last, round, logger);
+            return Ok(Some((probe_name, crf)));
         }
 
-        if search_min > search_max {
-            break;
-        }
+        narrow(ctx, &config, last, &mut search_min, &mut search_max);
     }
 
     probes.sort_unstable_by(|a, b| {
         let diff_a = (a.score - config.target).abs();
         let diff_b = (b.score - config.target).abs();
-        diff_a.partial_cmp(&diff_b).unwrap()
+        guard_ok(ctx, b).cmp(&guard_ok(ctx, a)).then(diff_a.partial_cmp(&diff_b).unwrap())
     });
 
-    if let Some(log) = logger {
-        let mut l = log.lock().unwrap();
-        l.push(ProbeLog {
-            chunk_idx: ctx.chunk.idx,
-            probes: probes.iter().map(|p| (p.crf, p.score)).collect(),
-            final_crf: probes[0].crf,
-            final_score: probes[0].score,
-            round: 10,
-        });
-    }
+    let Some(best) = probes.first() else { return Ok(None) };
+    record(ctx, &probes, best, config.max_rounds + 1, logger);
 
-    if ctx.use_cvvdp {
-        crate::svt::TQ_SCORES
-            .get_or_init(|| std::sync::Mutex::new(Vec::new()))
-            .lock()
-            .unwrap()
-            .push(probes[0].score);
-    } else {
-        crate::svt::TQ_SCORES
-            .get_or_init(|| std::sync::Mutex::new(Vec::new()))
-            .lock()
-            .unwrap()
-            .extend_from_slice(&probes[0].frame_scores);
+    let name = probe_name(ctx.idx, best.crf);
+    if !ctx.work_dir.join("split").join(&name).exists() {
+        encode_probe(ctx, best.crf, Some(best.score))?;
     }
-
-    probes.first().map(|p| format!("{:04}_{:.2}.ivf", ctx.chunk.idx, p.crf))
+    Ok(Some((name, best.crf)))
 }
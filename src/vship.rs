@@ -1,5 +1,9 @@
+use std::cell::RefCell;
 use std::ptr;
 
+use crate::ssimu2::Ssimu2Cpu;
+use crate::xpsnr::Xpsnr;
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct VshipSSIMU2Handler {
@@ -155,6 +159,7 @@ enum VshipException {
 }
 
 unsafe extern "C" {
+    fn Vship_GetDeviceCount(number: *mut i32) -> VshipException;
     fn Vship_SetDevice(gpu_id: i32) -> VshipException;
     fn Vship_SSIMU2Init(
         handler: *mut VshipSSIMU2Handler,
@@ -211,16 +216,25 @@ unsafe extern "C" {
     fn Vship_GetErrorMessage(exception: VshipException, out_msg: *mut i8, len: i32) -> i32;
 }
 
+pub fn device_count() -> i32 {
+    let mut count = 0;
+    let ret = unsafe { Vship_GetDeviceCount(&raw mut count) };
+    if ret as i32 == 0 { count } else { 0 }
+}
+
 pub struct VshipProcessor {
     handler: Option<VshipSSIMU2Handler>,
     cvvdp_handler: Option<VshipCVVDPHandler>,
     butteraugli_handler: Option<VshipButteraugliHandler>,
+    cpu: Option<Ssimu2Cpu>,
+    xpsnr: Option<RefCell<Xpsnr>>,
 }
 
 impl VshipProcessor {
     pub fn new(
         width: u32,
         height: u32,
+        target: Option<(u32, u32)>,
         is_10bit: bool,
         matrix: Option<i32>,
         transfer: Option<i32>,
@@ -228,18 +242,19 @@ impl VshipProcessor {
         color_range: Option<i32>,
         chroma_sample_position: Option<i32>,
         fps: f32,
-        use_cvvdp: bool,
-        use_butteraugli: bool,
+        (use_ssimu2, use_cvvdp, use_butteraugli): (bool, bool, bool),
+        device: i32,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         unsafe {
-            let ret = Vship_SetDevice(0);
+            let ret = Vship_SetDevice(device);
             if ret as i32 != 0 {
-                return Err("Failed to set VSHIP device".into());
+                return Err(format!("Failed to set VSHIP device {device}").into());
             }
 
             let src_colorspace = create_yuv_colorspace(
                 width,
                 height,
+                target,
                 is_10bit,
                 matrix,
                 transfer,
@@ -251,6 +266,7 @@ impl VshipProcessor {
             let dis_colorspace = create_yuv_colorspace(
                 width,
                 height,
+                target,
                 true,
                 matrix,
                 transfer,
@@ -259,7 +275,7 @@ impl VshipProcessor {
                 chroma_sample_position,
             );
 
-            let handler = if !use_cvvdp && !use_butteraugli {
+            let handler = if use_ssimu2 {
                 let mut handler = std::mem::zeroed::<VshipSSIMU2Handler>();
                 let ret =
                     Vship_SSIMU2Init(ptr::from_mut(&mut handler), src_colorspace, dis_colorspace);
@@ -316,10 +332,53 @@ impl VshipProcessor {
                 None
             };
 
-            Ok(Self { handler, cvvdp_handler, butteraugli_handler })
+            Ok(Self { handler, cvvdp_handler, butteraugli_handler, cpu: None, xpsnr: None })
+        }
+    }
+
+    pub fn cpu(
+        width: u32,
+        height: u32,
+        is_10bit: bool,
+        matrix: Option<i32>,
+        color_range: Option<i32>,
+    ) -> Self {
+        Self {
+            handler: None,
+            cvvdp_handler: None,
+            butteraugli_handler: None,
+            cpu: Some(Ssimu2Cpu::new(width, height, is_10bit, matrix, color_range)),
+            xpsnr: None,
+        }
+    }
+
+    pub fn xpsnr(width: u32, height: u32, is_10bit: bool) -> Self {
+        Self {
+            handler: None,
+            cvvdp_handler: None,
+            butteraugli_handler: None,
+            cpu: None,
+            xpsnr: Some(RefCell::new(Xpsnr::new(width, height, is_10bit))),
         }
     }
 
+    pub fn reset_xpsnr(&self) {
+        if let Some(x) = &self.xpsnr {
+            x.borrow_mut().reset();
+        }
+    }
+
+    pub fn compute_xpsnr(
+        &self,
+        planes1: [*const u8; 3],
+        planes2: [*const u8; 3],
+        line_sizes1: [i64; 3],
+        line_sizes2: [i64; 3],
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        let x = self.xpsnr.as_ref().ok_or("XPSNR not initialized")?;
+        Ok(x.borrow_mut().compute(planes1, planes2, line_sizes1, line_sizes2))
+    }
+
     pub fn compute_ssimulacra2(
         &self,
         planes1: [*const u8; 3],
@@ -327,6 +386,10 @@ impl VshipProcessor {
         line_sizes1: [i64; 3],
         line_sizes2: [i64; 3],
     ) -> Result<f64, Box<dyn std::error::Error>> {
+        if let Some(cpu) = &self.cpu {
+            return Ok(cpu.compute(planes1, planes2, line_sizes1, line_sizes2));
+        }
+
         unsafe {
             let mut score = 0.0;
             let ret = Vship_ComputeSSIMU2(
@@ -443,6 +506,7 @@ impl Drop for VshipProcessor {
 fn create_yuv_colorspace(
     width: u32,
     height: u32,
+    target: Option<(u32, u32)>,
     is_10bit: bool,
     matrix: Option<i32>,
     transfer: Option<i32>,
@@ -495,8 +559,8 @@ fn create_yuv_colorspace(
     VshipColorspace {
         width: i64::from(width),
         height: i64::from(height),
-        target_width: -1,
-        target_height: -1,
+        target_width: target.map_or(-1, |(w, _)| i64::from(w)),
+        target_height: target.map_or(-1, |(_, h)| i64::from(h)),
         sample: sample_val,
         range: range_val,
         subsampling: VshipChromaSubsample { subw: 1, subh: 1 },
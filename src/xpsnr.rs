@@ -0,0 +1,135 @@
+pub struct Xpsnr {
+    width: usize,
+    height: usize,
+    src_10bit: bool,
+    block: usize,
+    avg_act: f64,
+    prev: Vec<i32>,
+}
+
+impl Xpsnr {
+    pub fn new(width: u32, height: u32, src_10bit: bool) -> Self {
+        let r = f64::from(width) * f64::from(height) / (3840.0 * 2160.0);
+        Self {
+            width: width as usize,
+            height: height as usize,
+            src_10bit,
+            block: ((32.0 * r.sqrt() + 0.5) as usize * 4).max(4),
+            avg_act: (16.0 * f64::from(1 << 11) / r.sqrt().max(0.00001)).sqrt(),
+            prev: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.prev.clear();
+    }
+
+    pub fn compute(
+        &mut self,
+        planes1: [*const u8; 3],
+        planes2: [*const u8; 3],
+        line_sizes1: [i64; 3],
+        line_sizes2: [i64; 3],
+    ) -> f64 {
+        let (w, h) = (self.width, self.height);
+        let src = read_planes(planes1, line_sizes1, w, h, self.src_10bit);
+        let dis = read_planes(planes2, line_sizes2, w, h, true);
+
+        let org = &src[0];
+        let px = |x: isize, y: isize| {
+            org[y.clamp(0, h as isize - 1) as usize * w + x.clamp(0, w as isize - 1) as usize]
+        };
+
+        let b = self.block;
+        let mut wsse = [0.0; 3];
+
+        for by in (0..h).step_by(b) {
+            for bx in (0..w).step_by(b) {
+                let (ey, ex) = ((by + b).min(h), (bx + b).min(w));
+                let mut sad = 0u64;
+
+                for y in by..ey {
+                    for x in bx..ex {
+                        let (xi, yi) = (x as isize, y as isize);
+                        let hp = 12 * px(xi, yi)
+                            - 2 * (px(xi - 1, yi)
+                                + px(xi + 1, yi)
+                                + px(xi, yi - 1)
+                                + px(xi, yi + 1))
+                            - (px(xi - 1, yi - 1)
+                                + px(xi + 1, yi - 1)
+                                + px(xi - 1, yi + 1)
+                                + px(xi + 1, yi + 1));
+                        sad += u64::from(hp.unsigned_abs());
+                        if let Some(&p) = self.prev.get(y * w + x) {
+                            sad += 2 * u64::from((org[y * w + x] - p).unsigned_abs());
+                        }
+                    }
+                }
+
+                let n = ((ey - by) * (ex - bx)) as f64;
+                let act = (sad as f64 / (4.0 * n)).max(16.0);
+                let weight = self.avg_act / act;
+
+                wsse[0] += weight * sse(org, &dis[0], w, bx..ex, by..ey);
+                let cw = w / 2;
+                wsse[1] += weight * sse(&src[1], &dis[1], cw, bx / 2..ex / 2, by / 2..ey / 2);
+                wsse[2] += weight * sse(&src[2], &dis[2], cw, bx / 2..ex / 2, by / 2..ey / 2);
+            }
+        }
+
+        self.prev.clone_from(org);
+
+        let luma = (w * h) as f64;
+        let mse = (4.0 * wsse[0] / luma + wsse[1] / (luma / 4.0) + wsse[2] / (luma / 4.0)) / 6.0;
+        if mse <= 0.0 { 100.0 } else { (10.0 * (1023.0 * 1023.0 / mse).log10()).min(100.0) }
+    }
+}
+
+fn sse(
+    a: &[i32],
+    b: &[i32],
+    stride: usize,
+    xs: std::ops::Range<usize>,
+    ys: std::ops::Range<usize>,
+) -> f64 {
+    let mut sum = 0u64;
+    for y in ys {
+        for x in xs.clone() {
+            let d = a[y * stride + x] - b[y * stride + x];
+            sum += u64::from((d * d).unsigned_abs());
+        }
+    }
+    sum as f64
+}
+
+fn read_planes(
+    planes: [*const u8; 3],
+    strides: [i64; 3],
+    w: usize,
+    h: usize,
+    high_bit: bool,
+) -> [Vec<i32>; 3] {
+    let bps = if high_bit { 2 } else { 1 };
+    let shift = if high_bit { 0 } else { 2 };
+
+    std::array::from_fn(|p| {
+        let (pw, ph) = if p == 0 { (w, h) } else { (w / 2, h / 2) };
+        let stride = strides[p] as usize;
+        let data = unsafe { std::slice::from_raw_parts(planes[p], stride * (ph - 1) + pw * bps) };
+
+        let mut out = Vec::with_capacity(pw * ph);
+        for y in 0..ph {
+            let row = &data[y * stride..];
+            for x in 0..pw {
+                let v = if high_bit {
+                    i32::from(u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]))
+                } else {
+                    i32::from(row[x])
+                };
+                out.push(v << shift);
+            }
+        }
+        out
+    })
+}